@@ -1,6 +1,9 @@
 pub mod applied_revision;
 pub mod migrate_store;
 pub mod migration;
+pub mod migration_status;
+#[cfg(feature = "postgres")]
+pub mod postgres_client;
 #[cfg(feature = "postgres")]
 pub mod postgres_revision_storage;
 pub mod revision;
@@ -8,9 +11,13 @@ pub mod revision_list;
 
 //pub use migration::{migrate_database, reset_database};
 use crate::migrate_store::RevisionDatabase;
+#[cfg(feature = "postgres")]
+use crate::postgres_client::IntoPostgresClient;
+#[cfg(feature = "postgres")]
 use crate::postgres_revision_storage::PostgresRevisionStorage;
 use bon::bon;
 pub use migration::Migration;
+pub use migration_status::MigrationStatus;
 pub use revision::Revision;
 pub use revision_list::{RevisionList, RevisionStatus};
 
@@ -41,17 +48,21 @@ pub struct MigrationBuilder;
 #[bon]
 impl MigrationBuilder {
     /// Create a migration against a postgres pool with the specified revisions this
-    /// requreis the 'postgres' feature
+    /// requreis the 'postgres' feature. The pool can be a deadpool or bb8 pool (with
+    /// the 'bb8' feature) or a single `tokio_postgres::Client`
     #[builder(finish_fn = build)]
     #[cfg(feature = "postgres")]
-    pub fn postgres(
+    pub fn postgres<C>(
         revisions: &'static [Revision],
-        database_pool: &deadpool_postgres::Pool,
-    ) -> Migration<RevisionDatabase<PostgresRevisionStorage>> {
-        let storage = PostgresRevisionStorage::new(database_pool);
+        database_pool: C,
+    ) -> Migration<RevisionDatabase<PostgresRevisionStorage<C::Client>>>
+    where
+        C: IntoPostgresClient,
+    {
+        let storage = PostgresRevisionStorage::with_client(database_pool.into_client());
         let store = RevisionDatabase::builder().storage(storage).build();
 
-        Migration::<RevisionDatabase<PostgresRevisionStorage>>::builder()
+        Migration::<RevisionDatabase<PostgresRevisionStorage<C::Client>>>::builder()
             .store(store)
             .revisions(revisions)
             .build()
@@ -5,6 +5,7 @@ use tracing::instrument;
 use super::revision_list::{RevisionList, RevisionStatus};
 use crate::Revision;
 use crate::migrate_store::RevisionStore;
+use crate::migration_status::MigrationStatus;
 
 pub struct Migration<S> {
     store: S,
@@ -21,15 +22,64 @@ where
         Self { store, revisions }
     }
 
+    /// True when any of the revisions have not been applied to the database, if the
+    /// applied revisions cannot be determined a migration is assumed to be needed
     #[instrument(level = "info", skip_all, fields(revisions = self.revisions.revision_list()))]
     pub async fn needs_migration(&self) -> bool {
-        let applied_revisions = self.store.applied_revisions().await.unwrap_or_default();
-        self.revisions.iter().all(|revision| {
-            applied_revisions
-                .iter()
-                .find(|appled_revision| appled_revision.revision() == revision.revision())
-                .is_some()
-        })
+        match self.status().await {
+            MigrationStatus::Applied { .. } => false,
+            MigrationStatus::Unknown { .. } => true,
+            status => status.is_pending(),
+        }
+    }
+
+    /// Compares the applied revisions against the revisions of this migration and
+    /// reports the state of the database
+    #[instrument(level = "info", skip_all, fields(revisions = self.revisions.revision_list()))]
+    pub async fn status(&self) -> MigrationStatus {
+        let applied_revisions = match self.store.applied_revisions().await {
+            Ok(applied_revisions) => applied_revisions,
+            Err(e) => {
+                tracing::error!(
+                    error = e.to_string(),
+                    "failed to retrieve applied revisions for status"
+                );
+                return MigrationStatus::Unknown {
+                    error: e.to_string(),
+                };
+            }
+        };
+
+        let (applied, pending): (Vec<&Revision>, Vec<&Revision>) = self
+            .revisions
+            .iter()
+            .partition(|revision| applied_revisions.contains_revision(revision.revision()));
+
+        let applied = applied
+            .into_iter()
+            .map(|revision| revision.revision().to_owned())
+            .collect::<Vec<String>>();
+        let pending = pending
+            .into_iter()
+            .map(|revision| revision.revision().to_owned())
+            .collect::<Vec<String>>();
+        let unknown = applied_revisions
+            .iter()
+            .filter(|applied| !self.revisions.contains_revision(applied.revision()))
+            .map(|applied| applied.revision().to_owned())
+            .collect::<Vec<String>>();
+
+        if !unknown.is_empty() {
+            MigrationStatus::Drifted {
+                applied,
+                pending,
+                unknown,
+            }
+        } else if !pending.is_empty() {
+            MigrationStatus::Pending { applied, pending }
+        } else {
+            MigrationStatus::Applied { revisions: applied }
+        }
     }
 
     #[instrument(level = "info", skip_all, fields(revisions = self.revisions.revision_list()))]
@@ -99,7 +149,7 @@ where
             .collect::<Vec<Revision>>();
 
         let (to_revert, applied) = if let Some(count) = revisions {
-            let start = (applied.len() - count).max(0);
+            let start = applied.len().saturating_sub(count);
             (&applied[start..], &applied[..start])
         } else {
             (applied.as_slice(), &[] as &[Revision])
@@ -336,4 +386,95 @@ mod test {
 
         migration.reset().await.unwrap();
     }
+
+    #[test(tokio::test)]
+    async fn status_applied() {
+        static REVS: [Revision; 2] = [revision!("1"), revision!("2")];
+
+        let mut mock = MockRevisionStore::new();
+        mock.expect_applied_revisions()
+            .returning(|| Ok(vec![applied_revision!("2"), applied_revision!("1")]));
+
+        let migration = Migration::builder()
+            .store(mock)
+            .revisions(REVS.as_slice())
+            .build();
+
+        assert_eq!(
+            MigrationStatus::Applied {
+                revisions: vec![String::from("1"), String::from("2")]
+            },
+            migration.status().await
+        );
+        assert!(!migration.needs_migration().await);
+    }
+
+    #[test(tokio::test)]
+    async fn status_pending() {
+        static REVS: [Revision; 3] = [revision!("1"), revision!("2"), revision!("3")];
+
+        let mut mock = MockRevisionStore::new();
+        mock.expect_applied_revisions()
+            .returning(|| Ok(vec![applied_revision!("1")]));
+
+        let migration = Migration::builder()
+            .store(mock)
+            .revisions(REVS.as_slice())
+            .build();
+
+        assert_eq!(
+            MigrationStatus::Pending {
+                applied: vec![String::from("1")],
+                pending: vec![String::from("2"), String::from("3")],
+            },
+            migration.status().await
+        );
+        assert!(migration.needs_migration().await);
+    }
+
+    #[test(tokio::test)]
+    async fn status_drifted() {
+        static REVS: [Revision; 1] = [revision!("1")];
+
+        let mut mock = MockRevisionStore::new();
+        mock.expect_applied_revisions()
+            .returning(|| Ok(vec![applied_revision!("1"), applied_revision!("2")]));
+
+        let migration = Migration::builder()
+            .store(mock)
+            .revisions(REVS.as_slice())
+            .build();
+
+        assert_eq!(
+            MigrationStatus::Drifted {
+                applied: vec![String::from("1")],
+                pending: vec![],
+                unknown: vec![String::from("2")],
+            },
+            migration.status().await
+        );
+        assert!(!migration.needs_migration().await);
+    }
+
+    #[test(tokio::test)]
+    async fn status_unknown() {
+        static REVS: [Revision; 1] = [revision!("1")];
+
+        let mut mock = MockRevisionStore::new();
+        mock.expect_applied_revisions()
+            .returning(|| Err(anyhow!("connection refused")));
+
+        let migration = Migration::builder()
+            .store(mock)
+            .revisions(REVS.as_slice())
+            .build();
+
+        assert_eq!(
+            MigrationStatus::Unknown {
+                error: String::from("connection refused")
+            },
+            migration.status().await
+        );
+        assert!(migration.needs_migration().await);
+    }
 }
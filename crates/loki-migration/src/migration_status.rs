@@ -0,0 +1,94 @@
+use serde::Serialize;
+
+/// The state of the database schema compared with the revisions compiled into the
+/// running build, serializes into a tagged JSON object so it can be returned from a
+/// health check.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum MigrationStatus {
+    /// Every revision known to the build has been applied
+    Applied { revisions: Vec<String> },
+    /// Some of the revisions known to the build have not been applied yet
+    Pending {
+        applied: Vec<String>,
+        pending: Vec<String>,
+    },
+    /// The database contains revisions this build does not know about, usually this
+    /// means a newer build has migrated the database
+    Drifted {
+        applied: Vec<String>,
+        pending: Vec<String>,
+        unknown: Vec<String>,
+    },
+    /// The applied revisions could not be retrieved
+    Unknown { error: String },
+}
+
+impl MigrationStatus {
+    /// True when the database schema matches the revisions of this build
+    pub fn is_applied(&self) -> bool {
+        matches!(self, Self::Applied { .. })
+    }
+
+    /// True when there are revisions which still need to be applied
+    pub fn is_pending(&self) -> bool {
+        match self {
+            Self::Pending { .. } => true,
+            Self::Drifted { pending, .. } => !pending.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Applied { revisions } => write!(f, "applied({})", revisions.len()),
+            Self::Pending { pending, .. } => write!(f, "pending({})", pending.join(";")),
+            Self::Drifted { unknown, .. } => write!(f, "drifted({})", unknown.join(";")),
+            Self::Unknown { error } => write!(f, "unknown({error})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serialize_applied() {
+        let status = MigrationStatus::Applied {
+            revisions: vec![String::from("v000")],
+        };
+
+        assert_eq!(
+            json!({"status": "applied", "revisions": ["v000"]}),
+            serde_json::to_value(&status).unwrap()
+        );
+    }
+
+    #[test]
+    fn serialize_unknown() {
+        let status = MigrationStatus::Unknown {
+            error: String::from("connection refused"),
+        };
+
+        assert_eq!(
+            json!({"status": "unknown", "error": "connection refused"}),
+            serde_json::to_value(&status).unwrap()
+        );
+    }
+
+    #[test]
+    fn drifted_pending() {
+        let status = MigrationStatus::Drifted {
+            applied: vec![String::from("v000")],
+            pending: vec![],
+            unknown: vec![String::from("v002")],
+        };
+
+        assert!(!status.is_applied());
+        assert!(!status.is_pending());
+    }
+}
@@ -7,16 +7,28 @@ use tracing::instrument;
 
 use crate::applied_revision::AppliedRevision;
 use crate::migrate_store::RevisionStorage;
+use crate::postgres_client::PostgresClient;
 
-pub struct PostgresRevisionStorage {
-    pool: Pool,
+pub struct PostgresRevisionStorage<C = Pool> {
+    client: C,
     ensure_table: OnceCell<Result<()>>,
 }
 
-impl PostgresRevisionStorage {
+impl PostgresRevisionStorage<Pool> {
     pub fn new(pool: &Pool) -> Self {
+        Self::with_client(pool.clone())
+    }
+}
+
+impl<C> PostgresRevisionStorage<C>
+where
+    C: PostgresClient,
+{
+    /// Creates the storage over any [PostgresClient], such as a bb8 pool or a single
+    /// `tokio_postgres::Client`
+    pub fn with_client(client: C) -> Self {
         Self {
-            pool: pool.clone(),
+            client,
             ensure_table: OnceCell::new(),
         }
     }
@@ -25,10 +37,8 @@ impl PostgresRevisionStorage {
     async fn ensure_migrations_table(&self) -> Result<()> {
         self.ensure_table
             .get_or_init(|| async move {
-                self.pool
-                    .get()
-                    .await?
-                    .execute(
+                self.client
+                    .batch_execute(
                         r#"
                         CREATE TABLE IF NOT EXISTS migrations (
                             id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
@@ -36,7 +46,6 @@ impl PostgresRevisionStorage {
                             timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW()
                         )
                         "#,
-                        &[],
                     )
                     .await
                     .with_context(|| "failed to ensure the migration table")?;
@@ -49,17 +58,18 @@ impl PostgresRevisionStorage {
     }
 }
 
-impl RevisionStorage for PostgresRevisionStorage {
+impl<C> RevisionStorage for PostgresRevisionStorage<C>
+where
+    C: PostgresClient,
+{
     type Row = tokio_postgres::Row;
 
     #[instrument(level = "debug", skip_all)]
     async fn query_applied(&self) -> Result<Vec<Self::Row>> {
         self.ensure_migrations_table().await?;
 
-        self.pool
-            .get()
-            .await?
-            .query("SELECT rev,timestamp FROM migrations", &[])
+        self.client
+            .query("SELECT rev,timestamp FROM migrations")
             .await
             .map_err(|e| anyhow!("failed to query for applied revisions {e:?}"))
     }
@@ -68,20 +78,10 @@ impl RevisionStorage for PostgresRevisionStorage {
     async fn execute(&self, sql_query: &str) -> Result<()> {
         self.ensure_migrations_table().await?;
 
-        let mut client = self.pool.get().await?;
-        let transaction = client.transaction().await?;
-
-        transaction
-            .batch_execute(sql_query)
+        self.client
+            .transaction(sql_query)
             .await
-            .with_context(|| format!("failed to execute statements"))?;
-
-        transaction
-            .commit()
-            .await
-            .with_context(|| "failed to commit transaction")?;
-
-        Ok(())
+            .with_context(|| "failed to execute statements")
     }
 }
 
@@ -1,11 +1,26 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
 use anyhow::{Context, Result, bail};
-use axum::{Extension, Router};
+use axum::{Extension, Router, http::request::Parts, middleware};
 use bon::bon;
-use tower_http::services::ServeDir;
+use futures::FutureExt;
+use loki_migration::MigrationStatus;
 
-use crate::{page_builder::PageBuilder, registry::Registry};
+use crate::{
+    assets::{AssetBundle, AssetDirectory, AssetManifest, asset_routes, precompress},
+    cache::{AuthorizeFn, CacheRegistry, cache_routes},
+    error_pages::{ErrorPages, error_pages, not_found},
+    export::StaticExport,
+    health::{MigrationStatusFn, health_routes},
+    i18n::{Locales, Messages, localized_routes},
+    invalidation::Invalidate,
+    page_builder::PageBuilder,
+    registry::Registry,
+    reload::{Reload, Reloadable},
+    server::{Compression, Server},
+    sitemap::{Robots, Sitemap, SitemapEntry, SitemapFn, sitemap_routes},
+    template_engine::{TemplateEngine, TemplateEngineFn},
+};
 
 pub struct Application {}
 
@@ -13,36 +28,155 @@ pub struct Application {}
 impl Application {
     #[builder(finish_fn = finish)]
     pub async fn run(
-        #[builder(field)] assets: Vec<String>,
+        #[builder(field)] assets: Vec<AssetDirectory>,
+        #[builder(field)] bundles: Vec<AssetBundle>,
         #[builder(field)] templates: Vec<String>,
+        #[builder(field)] engines: Vec<TemplateEngineFn>,
         #[builder(field)] routes: Router,
+        #[builder(field)] migration_status: Option<MigrationStatusFn>,
+        #[builder(field)] caches: Option<(Arc<CacheRegistry>, AuthorizeFn)>,
+        #[builder(field)] mut reloads: Vec<Arc<dyn Reload>>,
+        #[builder(field)] reload_caches: Vec<&'static dyn Invalidate>,
+        #[builder(field)] sitemap: Vec<SitemapFn>,
         port: u16,
+        /// The url the site is served from, used for the absolute urls of the sitemap
+        #[builder(into)]
+        site_url: Option<String>,
+        robots: Option<Robots>,
+        /// The layout of the error pages, without it the error templates are rendered
+        /// on their own
+        #[builder(into)]
+        error_layout: Option<String>,
+        /// Reloads the templates when they change, only available in debug builds with
+        /// the `hot-reload` feature
+        #[builder(default)]
+        hot_reload: bool,
+        /// Writes the pages of the site and its assets to the directory rather than
+        /// serving them
+        #[builder(into)]
+        export: Option<PathBuf>,
+        /// Serves the pages in the locales, negotiated for each request
+        locales: Option<Locales>,
+        /// Combines each bundle into a single minified file, by default only in release
+        /// builds so the files are served as they are while developing
+        #[builder(default = !cfg!(debug_assertions))]
+        combine_bundles: bool,
+        /// Writes a `.gz` and `.br` copy of the compressible assets alongside them, these
+        /// are served in place of the assets when present whether or not this is set
+        #[builder(default)]
+        precompress_assets: bool,
+        /// The responses compressed when sent, a minimum size and content types
+        compression: Option<Compression>,
     ) -> Result<()> {
-        let mut registry = Registry::new();
-        templates
-            .iter()
-            .map(|template_dir| registry.register_directory(template_dir))
-            .collect::<std::result::Result<Vec<()>, _>>()
-            .with_context(|| "failed to register templates")?;
-
-        let routes = assets.into_iter().try_fold(routes, |routes, asset_dir| {
-            let asset_path = PathBuf::from(asset_dir);
+        // reloaded before the registry so the templates link to the new fingerprints
+        let manifest = Reloadable::new({
+            let assets = assets.clone();
+            move || {
+                if precompress_assets {
+                    for directory in &assets {
+                        precompress(directory.path())?;
+                    }
+                }
+                AssetManifest::load(&assets, &bundles, combine_bundles)
+            }
+        })?;
+        reloads.push(Arc::new(manifest.clone()));
+
+        let registry = Reloadable::new({
+            let templates = templates.clone();
+            let locales = locales.clone();
+            let manifest = manifest.clone();
+            move || {
+                let engines = engines.iter().map(|engine| engine()).collect();
+                let mut registry = Registry::load(&templates, engines)
+                    .with_context(|| "failed to register templates")?;
+                registry.set_assets(manifest.get());
+                if let Some(locales) = &locales
+                    && let Some(catalogs) = locales.catalogs()
+                {
+                    registry.set_messages(Messages::load(catalogs, locales.default_locale())?);
+                }
+                Ok(registry)
+            }
+        })?;
+        reloads.push(Arc::new(registry.clone()));
+
+        for directory in &assets {
+            let asset_path = directory.path();
             if !asset_path.exists() {
                 bail!("Unable to locate assets {asset_path:?}");
             }
 
-            let Some(assets_path) = asset_path.file_stem() else {
-                bail!("unable to locate name of the assets dir {asset_path:?}");
-            };
+            let asset_path = asset_path
+                .canonicalize()
+                .unwrap_or(asset_path.to_path_buf());
+            tracing::info!(
+                "adding assets dir {} --> {asset_path:?}",
+                directory.mount()?
+            );
+        }
+        // unknown routes are rendered as the 404 page in the locale as well
+        let routes = routes.fallback(not_found);
+        let routes = match locales.clone() {
+            Some(locales) => localized_routes(routes, locales),
+            None => routes,
+        };
+        let routes = routes.merge(asset_routes(&assets, manifest.clone())?);
 
-            let asset_path = asset_path.canonicalize().unwrap_or(asset_path.clone());
-            tracing::info!("adding assets dir /{assets_path:?} --> {asset_path:?}");
-            let assets_service = ServeDir::new("./assets");
-            Ok(routes.nest_service("/assets", assets_service))
-        })?;
+        let routes = match migration_status {
+            Some(migration_status) => routes.merge(health_routes(migration_status)),
+            None => routes,
+        };
+
+        let routes = match caches {
+            Some((caches, authorize)) => routes.merge(cache_routes(caches, authorize)),
+            None => routes,
+        };
+
+        let sitemap = Sitemap::new(
+            registry.clone(),
+            sitemap,
+            site_url,
+            robots.unwrap_or_default(),
+        );
+        let routes = routes.merge(sitemap_routes(sitemap.clone()));
+
+        let errors = Arc::new(ErrorPages::new(registry.clone(), error_layout));
+        let routes = routes
+            .layer(Extension(PageBuilder::new(registry.clone())))
+            .layer(middleware::from_fn_with_state(errors, error_pages));
+
+        if let Some(directory) = export {
+            return StaticExport::new(directory, routes, locales)
+                .export(&registry.get(), &sitemap, &assets, &manifest.get())
+                .await;
+        }
 
-        let routes = routes.layer(Extension(PageBuilder::new(registry)));
-        crate::server::Server::serve(port, routes).await?;
+        let routes = match hot_reload {
+            #[cfg(all(debug_assertions, feature = "hot-reload"))]
+            true => {
+                let watched = templates
+                    .iter()
+                    .cloned()
+                    .chain(
+                        assets
+                            .iter()
+                            .map(|directory| directory.path().to_string_lossy().into_owned()),
+                    )
+                    .collect::<Vec<_>>();
+                crate::reload::watch::hot_reload(routes, &watched, reloads, reload_caches)?
+            }
+            #[cfg(not(all(debug_assertions, feature = "hot-reload")))]
+            true => {
+                tracing::warn!(
+                    "hot reload is only available in debug builds with the hot-reload feature"
+                );
+                drop((reloads, reload_caches));
+                routes
+            }
+            false => routes,
+        };
+        Server::serve(port, routes, compression.unwrap_or_default()).await?;
         Ok(())
     }
 }
@@ -51,14 +185,22 @@ impl<S> ApplicationRunBuilder<S>
 where
     S: application_run_builder::State,
 {
+    /// Serves the directory of assets, at `/` followed by its name unless it's given a
+    /// mount of its own
     pub fn assets<A>(mut self, asset: A) -> Self
     where
-        A: Into<String>,
+        A: Into<AssetDirectory>,
     {
         self.assets.push(asset.into());
         self
     }
 
+    /// Serves the files together, linked with the `bundle` helper
+    pub fn bundle(mut self, bundle: AssetBundle) -> Self {
+        self.bundles.push(bundle);
+        self
+    }
+
     pub fn templates<T>(mut self, templates: T) -> Self
     where
         T: Into<String>,
@@ -67,6 +209,16 @@ where
         self
     }
 
+    /// Renders the templates with the engine's extension, handlebars templates are
+    /// always supported
+    pub fn engine<F>(mut self, engine: F) -> Self
+    where
+        F: Fn() -> Box<dyn TemplateEngine> + Send + Sync + 'static,
+    {
+        self.engines.push(Arc::new(engine));
+        self
+    }
+
     pub fn extension<E>(mut self, extension: E) -> Self
     where
         E: Clone + Send + Sync + 'static,
@@ -75,6 +227,54 @@ where
         self
     }
 
+    /// Exposes the status returned by the function at `/healthz/migrations`
+    pub fn migrations<F, Fut>(mut self, status: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = MigrationStatus> + Send + 'static,
+    {
+        self.migration_status = Some(Arc::new(move || status().boxed()));
+        self
+    }
+
+    /// Exposes the registered caches at `/admin/caches` to the requests the function
+    /// authorizes, there's no way to expose them without a check
+    pub fn caches<F, Fut>(mut self, registry: Arc<CacheRegistry>, authorize: F) -> Self
+    where
+        F: Fn(&Parts) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.caches = Some((registry, Arc::new(move |parts| authorize(parts).boxed())));
+        self
+    }
+
+    /// Adds the urls returned by the function to `/sitemap.xml`, for pages which
+    /// don't have a template of their own
+    pub fn sitemap<F, Fut>(mut self, entries: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Vec<SitemapEntry>> + Send + 'static,
+    {
+        self.sitemap.push(Arc::new(move || entries().boxed()));
+        self
+    }
+
+    /// Reloads the value along with the templates when hot reload is enabled
+    pub fn reloadable<T>(mut self, reloadable: &Reloadable<T>) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.reloads.push(Arc::new(reloadable.clone()));
+        self
+    }
+
+    /// Clears the cache after the templates are reloaded, for caches holding
+    /// rendered pages
+    pub fn clear_on_reload(mut self, cache: &'static dyn Invalidate) -> Self {
+        self.reload_caches.push(cache);
+        self
+    }
+
     pub fn routes(mut self, routes: Router) -> Self {
         self.routes = self.routes.merge(routes);
         self
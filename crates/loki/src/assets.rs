@@ -0,0 +1,773 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use axum::{
+    Router,
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use bon::Builder;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use tower::ServiceExt;
+use tower_http::services::ServeDir;
+use walkdir::WalkDir;
+
+use crate::reload::Reloadable;
+use crate::template_engine::helpers;
+
+mod bundle;
+mod compress;
+
+pub use bundle::AssetBundle;
+use bundle::{Bundle, Combined};
+pub(crate) use compress::precompress;
+
+/// Fingerprinted urls never change so they're cached for a year
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// The original urls are revalidated with the last modified time on every use
+const REVALIDATE: &str = "no-cache";
+
+///
+/// A directory of assets along with the url prefix it's served at, by default the
+/// directory's name so `./assets` is served at `/assets`. Directories sharing a prefix
+/// are searched in the order they were added and the first with the file serves it.
+///
+/// ```rust,ignore
+/// AssetDirectory::builder()
+///     .path("./vendor")
+///     .mount("/assets")
+///     .cache_control("public, max-age=86400")
+///     .build()
+/// ```
+///
+#[derive(Debug, Clone, Builder)]
+pub struct AssetDirectory {
+    #[builder(into)]
+    path: PathBuf,
+    /// The url prefix the files are served at
+    #[builder(into)]
+    mount: Option<String>,
+    /// The `Cache-Control` of the files at their original urls, `no-cache` by default
+    /// so they're revalidated, fingerprinted urls are always cached for a year
+    #[builder(into)]
+    cache_control: Option<String>,
+}
+
+impl AssetDirectory {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The url prefix without a trailing slash, `/assets`
+    pub fn mount(&self) -> Result<String> {
+        let mount = match &self.mount {
+            Some(mount) => mount.trim_matches('/').to_owned(),
+            None => self
+                .path
+                .file_stem()
+                .with_context(|| {
+                    format!("unable to locate name of the assets dir {:?}", self.path)
+                })?
+                .to_string_lossy()
+                .into_owned(),
+        };
+        if mount.is_empty() {
+            bail!("the assets {:?} can't be served from the root", self.path);
+        }
+        Ok(format!("/{mount}"))
+    }
+
+    fn cache_control(&self) -> Result<HeaderValue> {
+        let cache_control = self.cache_control.as_deref().unwrap_or(REVALIDATE);
+        HeaderValue::from_str(cache_control).with_context(|| {
+            format!(
+                "invalid cache control '{cache_control}' for the assets {:?}",
+                self.path
+            )
+        })
+    }
+}
+
+impl From<&str> for AssetDirectory {
+    fn from(path: &str) -> Self {
+        Self::builder().path(path).build()
+    }
+}
+
+impl From<&String> for AssetDirectory {
+    fn from(path: &String) -> Self {
+        Self::builder().path(path).build()
+    }
+}
+
+impl From<String> for AssetDirectory {
+    fn from(path: String) -> Self {
+        Self::builder().path(path).build()
+    }
+}
+
+impl From<PathBuf> for AssetDirectory {
+    fn from(path: PathBuf) -> Self {
+        Self::builder().path(path).build()
+    }
+}
+
+/// A file within one of the asset directories
+#[derive(Debug, Clone)]
+pub(crate) struct Asset {
+    path: PathBuf,
+    /// The url with the fingerprint, `/assets/css/site.3fa9c2d1.css`
+    url: String,
+}
+
+impl Asset {
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+///
+/// The files of the asset directories named by a hash of their contents, a change to a
+/// file changes its url so browsers can cache each url forever. Templates link to the
+/// fingerprinted url with the `asset` helper, `{{asset "css/site.css"}}` is
+/// `/assets/css/site.3fa9c2d1.css`, while the original url keeps working for old links
+/// and anything outside the templates. The files are hashed when the application
+/// starts and again when the templates are reloaded, along with the [AssetBundle]s.
+///
+/// Paths given to the helpers are within the first directory's mount, unless they're
+/// the url of a file in another, `{{asset "/vendor/htmx.js"}}`.
+///
+#[derive(Debug, Default)]
+pub struct AssetManifest {
+    /// The mount of the first directory, which paths are relative to
+    mount: String,
+    /// The original url of each file, `/assets/css/site.css`
+    assets: BTreeMap<String, Asset>,
+    /// The fingerprinted url of each file and combined bundle mapped to its original url
+    originals: BTreeMap<String, String>,
+    /// The url of each bundle, `/assets/css/main.css`
+    bundles: BTreeMap<String, Bundle>,
+}
+
+impl AssetManifest {
+    /// Hashes every file of the directories, a file found in an earlier directory hides
+    /// one with the same url in a later one. The bundles are combined into a single file
+    /// when `combine` is set.
+    #[tracing::instrument(level = "info", skip_all)]
+    pub(crate) fn load(
+        directories: &[AssetDirectory],
+        bundles: &[AssetBundle],
+        combine: bool,
+    ) -> Result<Self> {
+        let mut manifest = Self {
+            mount: match directories.first() {
+                Some(directory) => directory.mount()?,
+                None => helpers::ASSET_PATH.to_owned(),
+            },
+            ..Default::default()
+        };
+        for directory in directories {
+            let mount = directory.mount()?;
+            let directory = directory.path();
+            for entry in WalkDir::new(directory) {
+                let entry =
+                    entry.with_context(|| format!("failed to read assets {directory:?}"))?;
+                if !entry.file_type().is_file() || compress::is_precompressed(entry.path()) {
+                    continue;
+                }
+
+                let relative = entry
+                    .path()
+                    .strip_prefix(directory)?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let url = format!("{mount}/{relative}");
+                if manifest.assets.contains_key(&url) {
+                    continue;
+                }
+
+                let contents = std::fs::read(entry.path())
+                    .with_context(|| format!("failed to read asset {:?}", entry.path()))?;
+                let asset = Asset {
+                    path: entry.path().to_path_buf(),
+                    url: fingerprint(&url, &contents),
+                };
+                manifest.originals.insert(asset.url.clone(), url.clone());
+                manifest.assets.insert(url, asset);
+            }
+        }
+
+        for bundle in bundles {
+            let (url, bundle) = Bundle::build(&manifest.mount, bundle, &manifest.assets, combine)?;
+            if let Some(combined) = &bundle.combined {
+                manifest.originals.insert(combined.url.clone(), url.clone());
+            }
+            manifest.bundles.insert(url, bundle);
+        }
+
+        tracing::info!(
+            "fingerprinted {} assets and {} bundles",
+            manifest.assets.len(),
+            manifest.bundles.len()
+        );
+        Ok(manifest)
+    }
+
+    /// The fingerprinted url of a path within the assets, `css/site.css`, the original
+    /// url is used for files which aren't in the assets
+    pub fn url(&self, path: &str) -> String {
+        let url = self.asset_url(path);
+        let url = self.assets.get(&url).map(Asset::url).unwrap_or(&url);
+        helpers::encode(url, b"/")
+    }
+
+    /// The urls linking to the bundle, the combined file or each of the bundle's files
+    pub fn bundle_urls(&self, name: &str) -> Option<Vec<String>> {
+        let bundle = self.bundles.get(&self.asset_url(name))?;
+        Some(match &bundle.combined {
+            Some(combined) => vec![helpers::encode(&combined.url, b"/")],
+            None => bundle
+                .files
+                .iter()
+                .map(|file| self.assets.get(file).map(Asset::url).unwrap_or(file))
+                .map(|url| helpers::encode(url, b"/"))
+                .collect(),
+        })
+    }
+
+    /// The original url of the path, either the url of a file or bundle in any of the
+    /// mounts or a path within the first
+    fn asset_url(&self, path: &str) -> String {
+        if path.starts_with('/')
+            && (self.assets.contains_key(path) || self.bundles.contains_key(path))
+        {
+            return path.to_owned();
+        }
+        format!("{}/{}", self.mount, path.trim_start_matches('/'))
+    }
+
+    /// The original url of a fingerprinted one
+    pub(crate) fn original(&self, url: &str) -> Option<&str> {
+        self.originals.get(url).map(String::as_str)
+    }
+
+    pub(crate) fn assets(&self) -> impl Iterator<Item = &Asset> {
+        self.assets.values()
+    }
+
+    /// The combined bundle served at the url, along with whether it's the
+    /// fingerprinted url
+    fn combined(&self, url: &str) -> Option<(&Combined, bool)> {
+        let (bundle, fingerprinted) = match self.bundles.get(url) {
+            Some(bundle) => (bundle, false),
+            None => (self.bundles.get(self.originals.get(url)?)?, true),
+        };
+        bundle
+            .combined
+            .as_ref()
+            .map(|combined| (combined, fingerprinted))
+    }
+
+    /// The combined bundles with their original urls
+    pub(crate) fn combined_bundles(&self) -> impl Iterator<Item = (&str, &Combined)> {
+        self.bundles.iter().filter_map(|(url, bundle)| {
+            bundle
+                .combined
+                .as_ref()
+                .map(|combined| (url.as_str(), combined))
+        })
+    }
+}
+
+/// The first bytes of the hash of the contents as hex
+fn content_hash(contents: &[u8]) -> String {
+    Sha256::digest(contents)[..4]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Adds the hash of the contents to the file name before its extension,
+/// `site.css` becomes `site.3fa9c2d1.css`
+fn fingerprint(url: &str, contents: &[u8]) -> String {
+    let hash = content_hash(contents);
+
+    let (directory, name) = url.rsplit_once('/').unwrap_or(("", url));
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{directory}/{stem}.{hash}.{extension}")
+        }
+        _ => format!("{directory}/{name}.{hash}"),
+    }
+}
+
+struct AssetService {
+    mount: String,
+    /// The directories served at the mount in the order they're searched, with the
+    /// `Cache-Control` of their files
+    directories: Vec<(ServeDir, HeaderValue)>,
+    manifest: Reloadable<AssetManifest>,
+}
+
+/// Serves each directory at its mount, fingerprinted urls are served from the original
+/// file and cached forever. When several directories share a mount the first holding
+/// the file serves it. The `.gz` or `.br` sibling of a file is served in its place to
+/// clients accepting the encoding.
+pub(crate) fn asset_routes(
+    directories: &[AssetDirectory],
+    manifest: Reloadable<AssetManifest>,
+) -> Result<Router> {
+    let mut mounts: Vec<(String, Vec<(ServeDir, HeaderValue)>)> = Vec::new();
+    for directory in directories {
+        let mount = directory.mount()?;
+        let files = ServeDir::new(directory.path())
+            .precompressed_br()
+            .precompressed_gzip();
+        let served = (files, directory.cache_control()?);
+        match mounts.iter_mut().find(|(existing, _)| *existing == mount) {
+            Some((_, directories)) => directories.push(served),
+            None => mounts.push((mount, vec![served])),
+        }
+    }
+
+    Ok(mounts
+        .into_iter()
+        .fold(Router::new(), |routes, (mount, directories)| {
+            let service = Arc::new(AssetService {
+                mount: mount.clone(),
+                directories,
+                manifest: manifest.clone(),
+            });
+            routes.nest_service(&mount, get(serve_asset).with_state(service))
+        }))
+}
+
+async fn serve_asset(State(assets): State<Arc<AssetService>>, mut request: Request) -> Response {
+    let path = percent_decode_str(request.uri().path()).decode_utf8_lossy();
+    let url = format!("{}{path}", assets.mount);
+
+    let manifest = assets.manifest.get();
+    if let Some((combined, fingerprinted)) = manifest.combined(&url) {
+        return combined_response(combined, fingerprinted, request.headers());
+    }
+
+    let original = manifest
+        .original(&url)
+        .and_then(|original| original.strip_prefix(&assets.mount))
+        .and_then(|original| helpers::encode(original, b"/").parse::<Uri>().ok());
+    let fingerprinted = match original {
+        Some(original) => {
+            *request.uri_mut() = original;
+            true
+        }
+        None => false,
+    };
+
+    // asset requests have no body so each directory gets a copy until one has the file
+    let (parts, _) = request.into_parts();
+    let mut response = StatusCode::NOT_FOUND.into_response();
+    let mut cache_control = None;
+    for (files, directory_cache_control) in &assets.directories {
+        let request = Request::from_parts(parts.clone(), Body::empty());
+        response = match files.clone().oneshot(request).await {
+            Ok(response) => response.into_response(),
+            Err(e) => match e {},
+        };
+        if response.status() != StatusCode::NOT_FOUND {
+            cache_control = Some(directory_cache_control);
+            break;
+        }
+    }
+
+    if let Some(cache_control) = cache_control
+        && (response.status().is_success() || response.status().is_redirection())
+    {
+        let headers = response.headers_mut();
+        let cache_control = match fingerprinted {
+            true => HeaderValue::from_static(IMMUTABLE),
+            false => cache_control.clone(),
+        };
+        headers.insert(header::CACHE_CONTROL, cache_control);
+        // the precompressed sibling may be served in place of the file
+        headers.append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+    response
+}
+
+/// Combined bundles are served from memory, the hash of the contents is their etag which
+/// is weak as the compression layer may encode the body differently per client
+fn combined_response(combined: &Combined, fingerprinted: bool, request: &HeaderMap) -> Response {
+    let hash = format!("\"{}\"", content_hash(&combined.contents));
+    let cache_control = match fingerprinted {
+        true => IMMUTABLE,
+        false => REVALIDATE,
+    };
+    let content_type = match combined.url.rsplit_once('.') {
+        Some((_, "css")) => "text/css",
+        Some((_, "js")) => "text/javascript",
+        _ => "application/octet-stream",
+    };
+
+    let not_modified = request
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == hash)
+        });
+    let headers = [
+        (header::CONTENT_TYPE, content_type.to_owned()),
+        (header::CACHE_CONTROL, cache_control.to_owned()),
+        (header::ETAG, format!("W/{hash}")),
+    ];
+    match not_modified {
+        true => (StatusCode::NOT_MODIFIED, headers).into_response(),
+        false => (headers, combined.contents.clone()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Registry;
+    use crate::template_engine::HandlebarsEngine;
+    use axum::{body::Body, body::to_bytes, http::StatusCode};
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    fn directory() -> TempDir {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(directory.path().join("css")).unwrap();
+        std::fs::write(directory.path().join("css/site.css"), "body {}").unwrap();
+        std::fs::write(directory.path().join("LICENSE"), "MIT").unwrap();
+        directory
+    }
+
+    fn mounted(directory: &Path) -> Vec<AssetDirectory> {
+        vec![
+            AssetDirectory::builder()
+                .path(directory)
+                .mount("/assets")
+                .build(),
+        ]
+    }
+
+    #[test]
+    fn fingerprint_names() {
+        assert_eq!(
+            "/assets/css/site.62368a1a.css",
+            fingerprint("/assets/css/site.css", b"body {}")
+        );
+        assert_eq!(
+            "/assets/js/app.min.62368a1a.js",
+            fingerprint("/assets/js/app.min.js", b"body {}")
+        );
+        assert_eq!(
+            "/assets/.hidden.62368a1a",
+            fingerprint("/assets/.hidden", b"body {}")
+        );
+        assert_eq!(
+            "/assets/LICENSE.62368a1a",
+            fingerprint("/assets/LICENSE", b"body {}")
+        );
+    }
+
+    #[test]
+    fn manifest_urls() {
+        let temp = directory();
+        let directory = temp.path().to_path_buf();
+        let manifest = AssetManifest::load(&mounted(&directory), &[], false).unwrap();
+
+        assert_eq!(
+            "/assets/css/site.62368a1a.css",
+            manifest.url("css/site.css")
+        );
+        assert_eq!(
+            "/assets/css/site.62368a1a.css",
+            manifest.url("/css/site.css")
+        );
+        assert_eq!(
+            "/assets/css/my%20theme.css",
+            manifest.url("css/my theme.css")
+        );
+        assert_eq!(
+            Some("/assets/css/site.css"),
+            manifest.original("/assets/css/site.62368a1a.css")
+        );
+        assert_eq!(None, manifest.original("/assets/css/site.css"));
+        assert_eq!(2, manifest.assets().count());
+
+        let mut engine = HandlebarsEngine::new();
+        engine
+            .handlebars_mut()
+            .register_template_string("layout", "{{asset \"css/site.css\"}}")
+            .unwrap();
+        let mut registry = Registry::with_engines(vec![Box::new(engine)]);
+        registry.set_assets(Arc::new(manifest));
+        assert_eq!(
+            "/assets/css/site.62368a1a.css",
+            registry
+                .render("layout", None, &Default::default())
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_assets() {
+        let temp = directory();
+        let directory = temp.path().to_path_buf();
+        let manifest = Reloadable::new({
+            let directory = directory.clone();
+            move || AssetManifest::load(&mounted(&directory), &[], false)
+        })
+        .unwrap();
+        let routes = asset_routes(&mounted(&directory), manifest).unwrap();
+
+        let request = |uri: &str| {
+            routes
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        let response = request("/assets/css/site.62368a1a.css").await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(IMMUTABLE, response.headers()[header::CACHE_CONTROL]);
+        assert_eq!(
+            "body {}",
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        );
+
+        let response = request("/assets/css/site.css").await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(REVALIDATE, response.headers()[header::CACHE_CONTROL]);
+
+        let response = request("/assets/css/site.00000000.css").await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert!(response.headers().get(header::CACHE_CONTROL).is_none());
+    }
+
+    #[tokio::test]
+    async fn serve_mounted_directories() {
+        let site = directory();
+        let shared = directory();
+        std::fs::write(shared.path().join("css/site.css"), "body { color: red; }").unwrap();
+        std::fs::write(shared.path().join("css/extra.css"), "p {}").unwrap();
+        let vendor_directory = directory();
+        let vendor = vendor_directory.path().join("htmx");
+        std::fs::create_dir_all(&vendor).unwrap();
+        std::fs::write(vendor.join("htmx.js"), "htmx").unwrap();
+
+        let directories = vec![
+            AssetDirectory::builder()
+                .path(site.path())
+                .mount("/assets/")
+                .build(),
+            AssetDirectory::builder()
+                .path(shared.path())
+                .mount("assets")
+                .build(),
+            AssetDirectory::builder()
+                .path(&vendor)
+                .cache_control("public, max-age=86400")
+                .build(),
+        ];
+        assert_eq!("/htmx", directories[2].mount().unwrap());
+        let manifest = Reloadable::new({
+            let directories = directories.clone();
+            move || AssetManifest::load(&directories, &[], false)
+        })
+        .unwrap();
+
+        // the first directory of a mount hides the same file in the next
+        assert_eq!(
+            "/assets/css/site.62368a1a.css",
+            manifest.get().url("css/site.css")
+        );
+        assert_eq!(
+            fingerprint("/assets/css/extra.css", b"p {}"),
+            manifest.get().url("css/extra.css")
+        );
+        let htmx = fingerprint("/htmx/htmx.js", b"htmx");
+        assert_eq!(htmx, manifest.get().url("/htmx/htmx.js"));
+
+        let routes = asset_routes(&directories, manifest).unwrap();
+        let request = |uri: &str| {
+            routes
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        let response = request("/assets/css/site.css").await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "body {}",
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        );
+
+        let response = request("/assets/css/extra.css").await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(REVALIDATE, response.headers()[header::CACHE_CONTROL]);
+        assert_eq!(
+            "p {}",
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        );
+
+        let response = request("/htmx/htmx.js").await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "public, max-age=86400",
+            response.headers()[header::CACHE_CONTROL]
+        );
+        let response = request(&htmx).await.unwrap();
+        assert_eq!(IMMUTABLE, response.headers()[header::CACHE_CONTROL]);
+
+        let response = request("/assets/css/missing.css").await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+        let root = AssetDirectory::builder()
+            .path(site.path())
+            .mount("/")
+            .build();
+        assert!(root.mount().is_err());
+    }
+
+    #[tokio::test]
+    async fn combine_bundles() {
+        let temp = directory();
+        let directory = temp.path().to_path_buf();
+        std::fs::write(
+            directory.join("css/theme.css"),
+            "/* theme */\n:root {\n  --red: #c00;\n}\n",
+        )
+        .unwrap();
+        let bundles = [AssetBundle::new(
+            "css/main.css",
+            ["css/theme.css", "css/site.css"],
+        )];
+
+        let separate = AssetManifest::load(&mounted(&directory), &bundles, false).unwrap();
+        assert_eq!(
+            Some(vec![
+                separate.url("css/theme.css"),
+                separate.url("css/site.css")
+            ]),
+            separate.bundle_urls("css/main.css")
+        );
+        assert_eq!(None, separate.bundle_urls("css/other.css"));
+        assert_eq!(0, separate.combined_bundles().count());
+
+        let combined_url = fingerprint("/assets/css/main.css", b":root{--red:#c00}body{}");
+        let manifest = Reloadable::new({
+            let directory = directory.clone();
+            move || AssetManifest::load(&mounted(&directory), &bundles, true)
+        })
+        .unwrap();
+        assert_eq!(
+            Some(vec![combined_url.clone()]),
+            manifest.get().bundle_urls("/css/main.css")
+        );
+
+        let mut engine = HandlebarsEngine::new();
+        engine
+            .handlebars_mut()
+            .register_template_string(
+                "layout",
+                "{{#each (bundle \"css/main.css\")}}{{{this}}};{{/each}}",
+            )
+            .unwrap();
+        let mut registry = Registry::with_engines(vec![Box::new(engine)]);
+        registry.set_assets(manifest.get());
+        assert_eq!(
+            format!("{combined_url};"),
+            registry
+                .render("layout", None, &Default::default())
+                .unwrap()
+        );
+
+        let routes = asset_routes(&mounted(&directory), manifest).unwrap();
+        let request = |uri: &str, etag: Option<&str>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            routes.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let response = request(&combined_url, None).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(IMMUTABLE, response.headers()[header::CACHE_CONTROL]);
+        assert_eq!("text/css", response.headers()[header::CONTENT_TYPE]);
+        assert_eq!(
+            ":root{--red:#c00}body{}",
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        );
+
+        let response = request("/assets/css/main.css", None).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(REVALIDATE, response.headers()[header::CACHE_CONTROL]);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert!(etag.starts_with("W/\""), "{etag}");
+        let response = request("/assets/css/main.css", Some(&etag)).await.unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+
+        let missing = [AssetBundle::new("css/main.css", ["css/missing.css"])];
+        assert!(AssetManifest::load(&mounted(&directory), &missing, false).is_err());
+    }
+
+    #[tokio::test]
+    async fn serve_precompressed() {
+        let temp = directory();
+        let directory = temp.path().to_path_buf();
+        std::fs::write(directory.join("css/site.css.br"), "brotli").unwrap();
+        let manifest = Reloadable::new({
+            let directory = directory.clone();
+            move || AssetManifest::load(&mounted(&directory), &[], false)
+        })
+        .unwrap();
+        assert_eq!(2, manifest.get().assets().count());
+        let routes = asset_routes(&mounted(&directory), manifest).unwrap();
+
+        let request = |encoding: &str| {
+            routes.clone().oneshot(
+                Request::builder()
+                    .uri("/assets/css/site.62368a1a.css")
+                    .header(header::ACCEPT_ENCODING, encoding)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let response = request("gzip, br").await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("br", response.headers()[header::CONTENT_ENCODING]);
+        assert_eq!("Accept-Encoding", response.headers()[header::VARY]);
+        assert_eq!(IMMUTABLE, response.headers()[header::CACHE_CONTROL]);
+        assert_eq!(
+            "brotli",
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        );
+
+        let response = request("gzip").await.unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(
+            "body {}",
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use axum::body::Bytes;
+
+use super::{Asset, fingerprint};
+
+///
+/// Files served as one, such as the stylesheets of a layout. When bundles are combined
+/// the files are concatenated in order, stylesheets are minified, and the result is
+/// served from the bundle's own fingerprinted url. Otherwise each file is linked on its
+/// own, which is easier to debug while developing.
+///
+/// ```rust,ignore
+/// AssetBundle::new("css/main.css", ["css/theme.css", "css/site.css"])
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct AssetBundle {
+    name: String,
+    files: Vec<String>,
+}
+
+impl AssetBundle {
+    /// The name is the bundle's path within the assets, the files are paths within the
+    /// assets as well
+    pub fn new<N, F, S>(name: N, files: F) -> Self
+    where
+        N: Into<String>,
+        F: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            files: files.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A bundle within the manifest, the original urls of its files along with the
+/// combined file when bundles are combined
+#[derive(Debug)]
+pub(crate) struct Bundle {
+    pub(crate) files: Vec<String>,
+    pub(crate) combined: Option<Combined>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Combined {
+    /// The url with the fingerprint, `/assets/css/main.3fa9c2d1.css`
+    pub(crate) url: String,
+    pub(crate) contents: Bytes,
+}
+
+impl Bundle {
+    /// Combines the files of the bundle, they have to be within the assets
+    pub(crate) fn build(
+        mount: &str,
+        bundle: &AssetBundle,
+        assets: &BTreeMap<String, Asset>,
+        combine: bool,
+    ) -> Result<(String, Self)> {
+        let url = format!("{mount}/{}", bundle.name.trim_start_matches('/'));
+        let files = bundle
+            .files
+            .iter()
+            .map(|file| format!("{mount}/{}", file.trim_start_matches('/')))
+            .collect::<Vec<_>>();
+
+        let mut contents = Vec::new();
+        for file in &files {
+            let asset = assets.get(file).with_context(|| {
+                format!("the bundle '{url}' includes '{file}' which isn't an asset")
+            })?;
+            if combine {
+                let file = std::fs::read(&asset.path)
+                    .with_context(|| format!("failed to read asset {:?}", asset.path))?;
+                contents.extend(file);
+                contents.push(b'\n');
+            }
+        }
+
+        let combined = combine.then(|| {
+            if url.ends_with(".css") {
+                contents = minify_css(&String::from_utf8_lossy(&contents)).into_bytes();
+            }
+            Combined {
+                url: fingerprint(&url, &contents),
+                contents: Bytes::from(contents),
+            }
+        });
+        Ok((url, Self { files, combined }))
+    }
+}
+
+/// Whitespace around these is never needed
+const SEPARATORS: &[char] = &['{', '}', ';', ',', '>'];
+
+/// Removes the comments and the whitespace which doesn't change the meaning of the
+/// stylesheet, strings are left as they are
+pub(crate) fn minify_css(css: &str) -> String {
+    let mut minified = String::with_capacity(css.len());
+    let mut space = false;
+    let mut chars = css.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            let mut previous = ' ';
+            for c in chars.by_ref() {
+                if previous == '*' && c == '/' {
+                    break;
+                }
+                previous = c;
+            }
+            space = true;
+            continue;
+        }
+
+        if space
+            && let Some(last) = minified.chars().last()
+            && !SEPARATORS.contains(&last)
+            && !matches!(last, ':' | '(')
+            && !SEPARATORS.contains(&c)
+            && c != ')'
+        {
+            minified.push(' ');
+        }
+        space = false;
+
+        if c == '}' && minified.ends_with(';') {
+            minified.pop();
+        }
+        minified.push(c);
+
+        if c == '"' || c == '\'' {
+            while let Some(next) = chars.next() {
+                minified.push(next);
+                if next == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        minified.push(escaped);
+                    }
+                } else if next == c {
+                    break;
+                }
+            }
+        }
+    }
+
+    minified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn minify_stylesheets() {
+        assert_eq!(
+            "a:hover,nav>li{color:red;margin:0 auto}",
+            minify_css(
+                "/* links */\na:hover ,\nnav  >  li {\n  color: red;\n  margin: 0 auto;\n}\n"
+            )
+        );
+        assert_eq!(
+            "div :first-child{width:calc(100% - 2px)}",
+            minify_css("div :first-child {\n\twidth: calc( 100% - 2px );\n}")
+        );
+        assert_eq!(
+            r#"a::after{content:"  /* kept */ \"  "}"#,
+            minify_css("a::after {\n  content: \"  /* kept */ \\\"  \";\n}")
+        );
+        assert_eq!(
+            "@media screen and (min-width:40em){main{padding:0}}",
+            minify_css("@media screen and (min-width: 40em) {\n  main { padding: 0; }\n}\n")
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use flate2::{Compression, write::GzEncoder};
+use walkdir::WalkDir;
+
+/// The extensions of the assets worth compressing, images and fonts already are
+const COMPRESSIBLE: &[&str] = &["css", "js", "mjs", "json", "svg", "txt", "xml", "html"];
+
+/// The extensions of the precompressed siblings served in place of the asset
+pub(crate) const PRECOMPRESSED: &[&str] = &["gz", "br"];
+
+/// Writes a `.gz` and `.br` sibling for each compressible file of the directory, the
+/// siblings are only rewritten when the file has changed since they were written
+#[tracing::instrument(level = "info", skip_all, fields(directory = ?directory))]
+pub(crate) fn precompress(directory: &Path) -> Result<usize> {
+    let mut compressed = 0;
+    for entry in WalkDir::new(directory) {
+        let entry = entry.with_context(|| format!("failed to read assets {directory:?}"))?;
+        let compressible = entry
+            .path()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| COMPRESSIBLE.contains(&extension));
+        if !entry.file_type().is_file() || !compressible {
+            continue;
+        }
+
+        let modified = entry.metadata()?.modified()?;
+        for extension in PRECOMPRESSED {
+            let sibling = sibling(entry.path(), extension);
+            let current = std::fs::metadata(&sibling)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|written| written >= modified);
+            if current {
+                continue;
+            }
+
+            let contents = std::fs::read(entry.path())
+                .with_context(|| format!("failed to read asset {:?}", entry.path()))?;
+            let compressed_contents = match *extension {
+                "gz" => gzip(&contents)?,
+                _ => brotli(&contents)?,
+            };
+            std::fs::write(&sibling, compressed_contents)
+                .with_context(|| format!("failed to write {sibling:?}"))?;
+            compressed += 1;
+        }
+    }
+
+    tracing::info!("compressed {compressed} assets");
+    Ok(compressed)
+}
+
+/// A precompressed file whose asset is alongside it, these are served in place of
+/// the asset rather than on their own
+pub(crate) fn is_precompressed(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| PRECOMPRESSED.contains(&extension))
+        && path.with_extension("").is_file()
+}
+
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(".");
+    sibling.push(extension);
+    PathBuf::from(sibling)
+}
+
+fn gzip(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(contents)?;
+    Ok(encoder.finish()?)
+}
+
+fn brotli(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+        encoder.write_all(contents)?;
+    }
+    Ok(compressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn precompress_assets() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        std::fs::create_dir_all(directory.join("css")).unwrap();
+        let css = "body { color: red; }\n".repeat(20);
+        std::fs::write(directory.join("css/site.css"), &css).unwrap();
+        std::fs::write(directory.join("logo.png"), [0x89, b'P', b'N', b'G']).unwrap();
+
+        assert_eq!(2, precompress(directory).unwrap());
+        assert!(!directory.join("logo.png.gz").exists());
+        assert!(is_precompressed(&directory.join("css/site.css.gz")));
+        assert!(!is_precompressed(&directory.join("css/site.css")));
+
+        let mut gzip = String::new();
+        flate2::read::GzDecoder::new(
+            std::fs::File::open(directory.join("css/site.css.gz")).unwrap(),
+        )
+        .read_to_string(&mut gzip)
+        .unwrap();
+        assert_eq!(css, gzip);
+
+        let mut brotli = String::new();
+        brotli::Decompressor::new(
+            std::fs::File::open(directory.join("css/site.css.br")).unwrap(),
+            4096,
+        )
+        .read_to_string(&mut brotli)
+        .unwrap();
+        assert_eq!(css, brotli);
+
+        // the siblings are current so nothing is written again
+        assert_eq!(0, precompress(directory).unwrap());
+    }
+}
@@ -1,12 +1,185 @@
+use std::any::Any;
+use std::convert::Infallible;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use tokio::sync::Mutex;
+use serde::Serialize;
+use tokio::sync::{Mutex, watch};
+
+mod keyed;
+mod registry;
+mod response;
+
+pub use keyed::KeyedCache;
+pub use registry::{AuthorizeFn, CacheRegistry, CacheStats, Inspect, cache_routes};
+pub use response::{ResponseCache, ResponseCacheLayer, ResponseCacheService};
+
+/// A fetch error shared as an `Arc<E>`, the error is type erased as the error type is
+/// chosen by each call rather than the cache
+type SharedError = Arc<dyn Any + Send + Sync>;
+
+/// The result of a fetch shared with the callers waiting on it
+type Landed<T> = Result<T, SharedError>;
+
+struct State<T> {
+    expires: DateTime<Utc>,
+    /// When a stale while revalidate cache starts refreshing the value in the background
+    refresh_at: DateTime<Utc>,
+    value: Option<T>,
+    /// The tags the value was stored with, see [Cache::try_fetch_tagged]
+    tags: Vec<String>,
+    /// The fetch currently in progress, callers which miss the cache wait on this
+    /// rather than starting their own fetch
+    in_flight: Option<watch::Receiver<Option<Landed<T>>>>,
+    /// Incremented when the cache is cleared so a fetch which started before the
+    /// clear doesn't store its (possibly stale) value
+    generation: u64,
+    /// When a fetched value was last stored
+    refreshed: Option<DateTime<Utc>>,
+    /// The last failed fetch, only remembered when the cache has a [Backoff]
+    failure: Option<Failure>,
+}
+
+struct Failure {
+    error: SharedError,
+    state: CacheFailure,
+}
+
+/// Describes the failures of a cache backing off, the application can use this to
+/// tell visitors the content may be out of date
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct CacheFailure {
+    /// The number of consecutive failed fetches
+    pub failures: u32,
+    /// When the first of the consecutive fetches failed
+    pub since: DateTime<Utc>,
+    /// Until this time callers receive the last error, or the last good value, rather
+    /// than fetching again
+    pub retry_at: DateTime<Utc>,
+}
+
+/// Configures a cache to remember failed fetches, see [Cache::with_backoff]
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    keep_last_value: bool,
+}
+
+impl Backoff {
+    /// Waits `initial` after the first failure doubling for each consecutive failure
+    /// up to `max`
+    pub const fn exponential(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            keep_last_value: false,
+        }
+    }
+
+    /// Serves the last good value, even once expired, while the fetch is failing
+    pub const fn keep_last_value(mut self) -> Self {
+        self.keep_last_value = true;
+        self
+    }
+
+    fn delay(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+impl<T> State<T>
+where
+    T: Clone,
+{
+    fn valid(&self) -> Option<T> {
+        if Utc::now() < self.expires {
+            self.value.clone()
+        } else {
+            None
+        }
+    }
+
+    /// The expired value if it's still within the grace period
+    fn graced(&self, grace: Duration) -> Option<T> {
+        let graced = self
+            .expires
+            .checked_add_signed(chrono::Duration::from_std(grace).ok()?)?;
+        if Utc::now() < graced {
+            self.value.clone()
+        } else {
+            None
+        }
+    }
+
+    /// True while a failed fetch is being remembered
+    fn backing_off(&self) -> bool {
+        self.failure
+            .as_ref()
+            .is_some_and(|failure| Utc::now() < failure.state.retry_at)
+    }
+
+    fn backing_off_state(&self) -> Option<CacheFailure> {
+        self.failure
+            .as_ref()
+            .filter(|_| self.backing_off())
+            .map(|failure| failure.state.clone())
+    }
+
+    fn fetching(&self) -> bool {
+        self.in_flight
+            .as_ref()
+            .is_some_and(|receiver| receiver.has_changed().is_ok())
+    }
+}
+
+/// Configures a cache to serve stale values, see [Cache::const_stale]
+#[derive(Debug, Clone, Copy)]
+struct Stale {
+    soft_ttl: Duration,
+    grace: Duration,
+}
+
+/// Counts how the cache is used, these are kept outside of the lock so reading the
+/// stats never waits on a fetch
+struct Metrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+}
 
 pub struct Cache<T> {
     ttl: Duration,
-    cache: Mutex<(DateTime<Utc>, Option<T>)>,
+    stale: Option<Stale>,
+    backoff: Option<Backoff>,
+    /// The tags carried by every value stored in the cache
+    tags: &'static [&'static str],
+    cache: Mutex<State<T>>,
+    metrics: Metrics,
+}
+
+/// The role of a caller which missed the cache
+enum Fetch<T> {
+    /// Another caller is fetching the value
+    Wait(watch::Receiver<Option<Landed<T>>>),
+    /// This caller is fetching the value and shares the result through the sender
+    Lead(watch::Sender<Option<Landed<T>>>, u64),
+    /// The last fetch failed and the cache is backing off
+    Failed(SharedError),
 }
 
 ///
@@ -14,126 +187,455 @@ pub struct Cache<T> {
 /// used to prevert refetching data when it's fairly static especially when the cahnges
 /// can clear the cache.
 ///
+/// The lock is only held while reading or updating the value, so readers of a valid
+/// value never wait on a fetch. When the value is missing or expired only one fetch
+/// runs at a time and every caller which misses the cache shares its result.
+///
 impl<T> Cache<T>
 where
     T: Clone + Debug,
 {
-    pub const fn const_once() -> Self {
+    const fn const_new(ttl: Duration, expires: DateTime<Utc>, stale: Option<Stale>) -> Self {
         Self {
-            ttl: Duration::ZERO,
-            cache: Mutex::const_new((DateTime::<Utc>::MAX_UTC, None)),
+            ttl,
+            stale,
+            backoff: None,
+            tags: &[],
+            cache: Mutex::const_new(State {
+                expires,
+                refresh_at: expires,
+                value: None,
+                tags: Vec::new(),
+                in_flight: None,
+                generation: 0,
+                refreshed: None,
+                failure: None,
+            }),
+            metrics: Metrics::new(),
         }
     }
 
+    pub const fn const_once() -> Self {
+        Self::const_new(Duration::ZERO, DateTime::<Utc>::MAX_UTC, None)
+    }
+
     pub const fn const_always() -> Self {
-        Self {
-            ttl: Duration::ZERO,
-            cache: Mutex::const_new((DateTime::<Utc>::MIN_UTC, None)),
-        }
+        Self::const_new(Duration::ZERO, DateTime::<Utc>::MIN_UTC, None)
     }
 
     pub const fn const_ttl(ttl: Duration) -> Self {
-        Self {
-            ttl,
-            cache: Mutex::const_new((DateTime::<Utc>::MIN_UTC, None)),
-        }
+        Self::const_new(ttl, DateTime::<Utc>::MIN_UTC, None)
+    }
+
+    /// Creates a stale while revalidate cache, once a value is older than `soft_ttl`
+    /// [Cache::try_fetch_stale] returns it immediately and refreshes it in the
+    /// background, once it's older than `hard_ttl` it must be fetched again. If
+    /// fetching fails the expired value is still served for the `grace` period.
+    pub const fn const_stale(soft_ttl: Duration, hard_ttl: Duration, grace: Duration) -> Self {
+        Self::const_new(
+            hard_ttl,
+            DateTime::<Utc>::MIN_UTC,
+            Some(Stale { soft_ttl, grace }),
+        )
+    }
+
+    /// Tags every value stored in the cache so they're cleared by
+    /// [Cache::invalidate_tag] along with everything else derived from the same data,
+    /// values can carry tags of their own with [Cache::try_fetch_tagged]
+    pub const fn with_tags(mut self, tags: &'static [&'static str]) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Remembers failed fetches so callers receive the error, or the last good value,
+    /// until the backoff expires rather than all retrying the fetch
+    pub const fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    pub fn tags(&self) -> &'static [&'static str] {
+        self.tags
     }
 
     pub async fn expired(&self) -> bool {
         let cache = self.cache.lock().await;
-        Utc::now() > cache.0
+        Utc::now() > cache.expires
     }
 
+    /// The usage of the cache along with the state of the cached value
+    pub async fn stats(&self) -> CacheStats {
+        let cache = self.cache.lock().await;
+        let cached = cache.value.is_some();
+        CacheStats {
+            hits: self.metrics.hits.load(Ordering::Relaxed),
+            misses: self.metrics.misses.load(Ordering::Relaxed),
+            errors: self.metrics.errors.load(Ordering::Relaxed),
+            cached,
+            last_refresh: cache.refreshed,
+            expires: (cached && cache.expires != DateTime::<Utc>::MAX_UTC).then_some(cache.expires),
+            failure: cache.backing_off_state(),
+        }
+    }
+
+    /// The failures of the cache while it's backing off, `None` once `retry_at` has
+    /// passed or a fetch succeeds. The failures are still counted until a fetch
+    /// succeeds so the next failure backs off for longer.
+    pub async fn failure(&self) -> Option<CacheFailure> {
+        let cache = self.cache.lock().await;
+        cache.backing_off_state()
+    }
+
+    /// Clears the value and any remembered failure, a fetch already in progress still
+    /// completes for the callers waiting on it but its value is not stored
     pub async fn clear(&self) {
         let mut cache = self.cache.lock().await;
-        cache.1.take();
+        Self::clear_state(&mut cache);
+    }
+
+    /// Clears the value if it or the cache carries the tag, returning true when it does
+    pub async fn invalidate_tag(&self, tag: &str) -> bool {
+        let mut cache = self.cache.lock().await;
+        let tagged = cache.value.is_some() && cache.tags.iter().any(|value_tag| value_tag == tag);
+        if !self.tags.contains(&tag) && !tagged {
+            return false;
+        }
+
+        Self::clear_state(&mut cache);
+        true
+    }
+
+    /// Stores the value carrying the tags, along with the tags of the cache
+    pub async fn insert_tagged(&self, value: T, tags: &[&str]) {
+        let mut cache = self.cache.lock().await;
+        self.store(&mut cache, value, Self::owned_tags(tags));
     }
 
-    pub async fn try_fetch<F, R, E>(&self, op: F) -> Result<T, E>
+    /// Returns the cached value or fetches it like [Cache::try_fetch], the fetched
+    /// value carries the tags along with the tags of the cache
+    pub async fn try_fetch_tagged<F, R, E>(&self, tags: &[&str], op: F) -> Result<T, Arc<E>>
     where
         R: Into<T>,
-        F: Future<Output = Result<R, E>> + 'static,
+        F: Future<Output = Result<R, E>>,
+        E: Send + Sync + 'static,
     {
-        let mut cache = self.cache.lock().await;
-        if Utc::now() < cache.0 && cache.1.is_some() {
-            return Ok(unsafe { cache.1.clone().unwrap_unchecked() });
+        self.fetch_with(Self::owned_tags(tags), op).await
+    }
+
+    /// Returns the cached value or fetches it, if the fetch fails the cached value is
+    /// dropped and every caller waiting on the fetch receives the same error, as do
+    /// the callers which miss the cache while it's backing off the failure
+    pub async fn try_fetch<F, R, E>(&self, op: F) -> Result<T, Arc<E>>
+    where
+        R: Into<T>,
+        F: Future<Output = Result<R, E>>,
+        E: Send + Sync + 'static,
+    {
+        self.try_fetch_tagged(&[], op).await
+    }
+
+    /// Returns the cached value even if it's stale, when the value is older than the
+    /// soft TTL a refresh is spawned so the next caller gets a fresh value. Without a
+    /// value, or once the value is older than the hard TTL, this waits on the fetch
+    /// just like [Cache::try_fetch].
+    ///
+    /// The refresh outlives the caller so the cache is shared through an [Arc], a static
+    /// cache can be shared with a `LazyLock<Arc<Cache<T>>>`.
+    pub async fn try_fetch_stale<F, R, E>(self: &Arc<Self>, op: F) -> Result<T, Arc<E>>
+    where
+        R: Into<T>,
+        F: Future<Output = Result<R, E>> + Send + 'static,
+        E: Send + Sync + 'static,
+        T: Send + Sync + 'static,
+    {
+        if self.stale.is_some() {
+            let mut cache = self.cache.lock().await;
+            if let Some(value) = cache.valid()
+                && Utc::now() >= cache.refresh_at
+                && !cache.fetching()
+                && !cache.backing_off()
+            {
+                let (sender, receiver) = watch::channel(None);
+                cache.in_flight.replace(receiver);
+                let generation = cache.generation;
+                // the refreshed value keeps the tags it was stored with
+                let tags = cache.tags.clone();
+                drop(cache);
+
+                self.metrics.hits.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!("serving stale value while refreshing");
+                let cache = self.clone();
+                tokio::spawn(async move {
+                    let _ = cache.lead(sender, generation, tags, op).await;
+                });
+                return Ok(value);
+            }
+        }
+
+        self.try_fetch(op).await
+    }
+
+    pub async fn fetch<F, R>(&self, op: F) -> T
+    where
+        F: Future<Output = R>,
+        R: Into<T>,
+    {
+        match self
+            .try_fetch(async move { Ok::<R, Infallible>(op.await) })
+            .await
+        {
+            Ok(value) => value,
+            Err(error) => match *error {},
         }
-        match op.await {
-            Ok(result) => {
-                let value = result.into();
-                cache.1.replace(value.clone());
+    }
+
+    pub async fn try_fetch_or_default<F, R, E>(&self, op: F) -> Result<T, E>
+    where
+        R: Into<T>,
+        F: Future<Output = Result<R, E>>,
+        E: Send + Sync + 'static,
+        T: Default,
+    {
+        match self.try_fetch(op).await {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                let mut cache = self.cache.lock().await;
                 if self.ttl != Duration::ZERO {
-                    cache.0 = Utc::now() + self.ttl;
+                    cache.expires = DateTime::<Utc>::MIN_UTC;
                 }
-                Ok(value)
-            }
-            Err(e) => {
-                cache.1.take();
-                Err(e)
+                Ok(T::default())
             }
         }
     }
 
-    pub async fn fetch<F, R>(&self, op: F) -> T
+    /// Returns the cached value or fetches it, sharing the fetch with the callers which
+    /// miss the cache while it's running
+    async fn fetch_with<F, R, E>(&self, tags: Vec<String>, op: F) -> Result<T, Arc<E>>
     where
-        F: Future<Output = R> + 'static,
         R: Into<T>,
-        F::Output: Into<T>,
+        F: Future<Output = Result<R, E>>,
+        E: Send + Sync + 'static,
     {
+        let mut backoff = true;
+        let (sender, generation) = loop {
+            match self.begin_fetch(backoff).await {
+                Ok(value) => return Ok(value),
+                Err(Fetch::Lead(sender, generation)) => break (sender, generation),
+                Err(Fetch::Failed(error)) => match error.downcast::<E>() {
+                    Ok(error) => return Err(error),
+                    // the failure has a different error type, fetch our own
+                    Err(_) => backoff = false,
+                },
+                Err(Fetch::Wait(receiver)) => match Self::wait(receiver).await {
+                    Some(Ok(value)) => return Ok(value),
+                    Some(Err(error)) => {
+                        if let Ok(error) = error.downcast::<E>() {
+                            return Err(error);
+                        }
+                        // the error has a different type, fetch our own
+                    }
+                    // the caller fetching the value went away, fetch our own
+                    None => {}
+                },
+            }
+        };
+
+        self.lead(sender, generation, tags, op).await
+    }
+
+    /// Returns the cached value if it's still valid, otherwise either joins the fetch
+    /// in progress or starts a new one. While backing off the last failure, or the last
+    /// good value, is returned instead.
+    async fn begin_fetch(&self, backoff: bool) -> Result<T, Fetch<T>> {
         let mut cache = self.cache.lock().await;
-        if Utc::now() < cache.0 && cache.1.is_some() {
-            return cache.1.clone().unwrap();
+        if let Some(value) = cache.valid() {
+            self.metrics.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
         }
 
-        let result = op.await.into();
-        cache.1.replace(result.clone());
-        if self.ttl != Duration::ZERO {
-            cache.0 = Utc::now() + self.ttl;
+        if backoff
+            && cache.backing_off()
+            && let Some(failure) = &cache.failure
+        {
+            if self.backoff.is_some_and(|backoff| backoff.keep_last_value)
+                && let Some(value) = &cache.value
+            {
+                self.metrics.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(value.clone());
+            }
+
+            self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+            return Err(Fetch::Failed(failure.error.clone()));
         }
 
-        result
+        self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+        // a closed channel means the caller fetching went away before finishing
+        if cache.fetching()
+            && let Some(receiver) = &cache.in_flight
+        {
+            return Err(Fetch::Wait(receiver.clone()));
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        cache.in_flight.replace(receiver);
+        Err(Fetch::Lead(sender, cache.generation))
     }
 
-    pub async fn try_fetch_or_default<F, R, E>(&self, op: F) -> Result<T, E>
+    /// Runs the fetch sharing the result with every caller waiting on it
+    async fn lead<F, R, E>(
+        &self,
+        sender: watch::Sender<Option<Landed<T>>>,
+        generation: u64,
+        tags: Vec<String>,
+        op: F,
+    ) -> Result<T, Arc<E>>
     where
         R: Into<T>,
-        F: Future<Output = Result<R, E>> + 'static,
-        T: Default,
+        F: Future<Output = Result<R, E>>,
+        E: Send + Sync + 'static,
+    {
+        let result = op.await.map(Into::into).map_err(Arc::new);
+        if result.is_err() {
+            self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let result = self.complete_fetch(generation, tags, result).await;
+
+        sender.send_replace(Some(match &result {
+            Ok(value) => Ok(value.clone()),
+            Err(error) => Err(error.clone()),
+        }));
+        result
+    }
+
+    /// Stores the result of a fetch unless the cache was cleared while fetching, when
+    /// the fetch fails an expired value within the grace period is returned instead
+    async fn complete_fetch<E>(
+        &self,
+        generation: u64,
+        tags: Vec<String>,
+        result: Result<T, Arc<E>>,
+    ) -> Result<T, Arc<E>>
+    where
+        E: Send + Sync + 'static,
     {
         let mut cache = self.cache.lock().await;
-        if Utc::now() < cache.0 && cache.1.is_some() {
-            return Ok(unsafe { cache.1.clone().unwrap_unchecked() });
+        let current = cache.generation == generation;
+        if current {
+            cache.in_flight.take();
         }
 
-        match op.await {
-            Ok(result) => {
-                let value = result.into();
-                cache.1.replace(value.clone());
-                if self.ttl != Duration::ZERO {
-                    cache.0 = Utc::now() + self.ttl;
+        match result {
+            Ok(value) => {
+                if current {
+                    self.store(&mut cache, value.clone(), tags);
                 }
                 Ok(value)
             }
-            Err(_) => {
-                cache.1.take();
-                if self.ttl != Duration::ZERO {
-                    cache.0 = DateTime::<Utc>::MIN_UTC;
+            Err(error) => {
+                if current && let Some(backoff) = self.backoff {
+                    let now = Utc::now();
+                    let failures = cache
+                        .failure
+                        .as_ref()
+                        .map(|failure| failure.state.failures + 1)
+                        .unwrap_or(1);
+                    let delay = backoff.delay(failures);
+                    tracing::warn!("cache fetch failed {failures} time(s), backing off {delay:?}");
+
+                    let since = cache
+                        .failure
+                        .as_ref()
+                        .map(|failure| failure.state.since)
+                        .unwrap_or(now);
+                    cache.failure.replace(Failure {
+                        error: error.clone(),
+                        state: CacheFailure {
+                            failures,
+                            since,
+                            retry_at: now + delay,
+                        },
+                    });
                 }
-                Ok(T::default())
+
+                if current && let Some(stale) = self.stale {
+                    // the next refresh waits out the soft TTL again rather than every
+                    // caller spawning a refresh against the failing fetch
+                    cache.refresh_at = Utc::now() + stale.soft_ttl;
+                }
+
+                if let Some(stale) = self.stale
+                    && let Some(value) = cache.graced(stale.grace)
+                {
+                    tracing::warn!("failed to refresh the cache, serving the stale value");
+                    return Ok(value);
+                }
+
+                if let Some(backoff) = self.backoff
+                    && backoff.keep_last_value
+                    && let Some(value) = &cache.value
+                {
+                    tracing::warn!("failed to refresh the cache, serving the last good value");
+                    return Ok(value.clone());
+                }
+
+                if current {
+                    cache.value.take();
+                }
+                Err(error)
             }
         }
     }
+
+    fn store(&self, cache: &mut State<T>, value: T, tags: Vec<String>) {
+        let now = Utc::now();
+        cache.value.replace(value);
+        cache.tags = tags;
+        cache.refreshed = Some(now);
+        if cache.failure.take().is_some() {
+            tracing::info!("the cache recovered after failing to fetch");
+        }
+        if self.ttl != Duration::ZERO {
+            cache.expires = now + self.ttl;
+        }
+        cache.refresh_at = match self.stale {
+            Some(stale) => now + stale.soft_ttl,
+            None => cache.expires,
+        };
+    }
+
+    fn clear_state(cache: &mut State<T>) {
+        cache.value.take();
+        cache.tags.clear();
+        cache.in_flight.take();
+        cache.failure.take();
+        cache.generation += 1;
+    }
+
+    fn owned_tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    /// Waits for another caller to finish fetching, `None` if it went away first
+    async fn wait(mut receiver: watch::Receiver<Option<Landed<T>>>) -> Option<Landed<T>> {
+        receiver
+            .wait_for(Option::is_some)
+            .await
+            .ok()
+            .and_then(|landed| landed.clone())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::{sync::Arc, time::Duration};
 
     use anyhow::{Result, anyhow};
     use mockall::automock;
 
-    use super::Cache;
+    use super::{Backoff, Cache};
 
     #[automock]
     trait Fetch {
@@ -386,4 +888,377 @@ mod tests {
             cache.fetch(async move { mock_clone.fetch() }).await
         );
     }
+
+    #[tokio::test]
+    async fn invalidate_tag() {
+        static NEWS: Cache<i32> = Cache::const_once().with_tags(&["news", "home"]);
+
+        assert_eq!(7, NEWS.fetch(async { 7 }).await);
+        assert!(!NEWS.invalidate_tag("events").await);
+        assert_eq!(7, NEWS.fetch(async { 11 }).await);
+
+        assert!(NEWS.invalidate_tag("home").await);
+        assert_eq!(11, NEWS.fetch(async { 11 }).await);
+    }
+
+    #[tokio::test]
+    async fn invalidate_value_tag() {
+        let cache = Cache::<i32>::const_once();
+
+        let fetched = cache
+            .try_fetch_tagged(&["article:7"], async { Ok::<_, Infallible>(7) })
+            .await;
+        assert_eq!(7, fetched.unwrap());
+        assert!(!cache.invalidate_tag("article:11").await);
+        assert_eq!(7, cache.fetch(async { 11 }).await);
+
+        assert!(cache.invalidate_tag("article:7").await);
+        assert!(!cache.invalidate_tag("article:7").await);
+        assert_eq!(11, cache.fetch(async { 11 }).await);
+        // the fetch replaced the value along with its tags
+        assert!(!cache.invalidate_tag("article:7").await);
+
+        cache.insert_tagged(13, &["article:13"]).await;
+        assert_eq!(13, cache.fetch(async { 17 }).await);
+        assert!(cache.invalidate_tag("article:13").await);
+        assert_eq!(17, cache.fetch(async { 17 }).await);
+    }
+
+    #[tokio::test]
+    async fn backoff() {
+        let cache = Cache::<i32>::const_once().with_backoff(Backoff::exponential(
+            Duration::from_millis(50),
+            Duration::from_millis(80),
+        ));
+        let fetches = AtomicUsize::new(0);
+        let fail = || async {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            Err::<i32, _>(anyhow!("database unavailable"))
+        };
+
+        assert!(cache.try_fetch(fail()).await.is_err());
+        let failed = cache.try_fetch(fail()).await;
+        assert_eq!("database unavailable", failed.unwrap_err().to_string());
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(cache.try_fetch(fail()).await.is_err());
+        assert_eq!(2, fetches.load(Ordering::SeqCst));
+
+        let failure = cache.failure().await.unwrap();
+        assert_eq!(2, failure.failures);
+        assert!(failure.retry_at - failure.since >= chrono::Duration::milliseconds(80));
+
+        // the delay is capped at the max
+        tokio::time::sleep(Duration::from_millis(90)).await;
+        assert_eq!(None, cache.failure().await);
+        assert_eq!(
+            7,
+            cache
+                .try_fetch(async { Ok::<_, anyhow::Error>(7) })
+                .await
+                .unwrap()
+        );
+        assert_eq!(None, cache.failure().await);
+    }
+
+    #[tokio::test]
+    async fn backoff_keep_last_value() {
+        let cache = Cache::<i32>::const_ttl(Duration::from_millis(10)).with_backoff(
+            Backoff::exponential(Duration::from_secs(60), Duration::from_secs(60))
+                .keep_last_value(),
+        );
+
+        assert_eq!(7, cache.fetch(async { 7 }).await);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let failed = cache
+            .try_fetch(async { Err::<i32, _>(anyhow!("database unavailable")) })
+            .await;
+        assert_eq!(7, failed.unwrap());
+        assert_eq!(7, cache.fetch(async { 11 }).await);
+        assert_eq!(1, cache.failure().await.unwrap().failures);
+
+        // clearing forgets the failure so the next caller fetches
+        cache.clear().await;
+        assert_eq!(None, cache.failure().await);
+        assert_eq!(11, cache.fetch(async { 11 }).await);
+    }
+
+    #[tokio::test]
+    async fn try_fetch_overlapped_misses() {
+        let cache = Cache::<String>::const_once();
+        let fetches = AtomicUsize::new(0);
+        let fetch = || async {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok::<_, anyhow::Error>("test-mock")
+        };
+
+        let (one, two, three) = tokio::join!(
+            cache.try_fetch(fetch()),
+            cache.try_fetch(fetch()),
+            cache.try_fetch(fetch())
+        );
+
+        assert_eq!("test-mock", one.unwrap());
+        assert_eq!("test-mock", two.unwrap());
+        assert_eq!("test-mock", three.unwrap());
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn try_fetch_overlapped_failure() {
+        let cache = Cache::<String>::const_once();
+        let fetches = AtomicUsize::new(0);
+        let fetch = || async {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Err::<&str, _>(anyhow!("database unavailable"))
+        };
+
+        let (one, two) = tokio::join!(cache.try_fetch(fetch()), cache.try_fetch(fetch()));
+
+        let (one, two) = (one.unwrap_err(), two.unwrap_err());
+        assert_eq!("database unavailable", one.to_string());
+        // the waiter receives the error the fetch returned
+        assert!(Arc::ptr_eq(&one, &two));
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn clear_during_fetch() {
+        let cache = Arc::new(Cache::<i32>::const_ttl(Duration::from_millis(100)));
+        assert_eq!(7, cache.fetch(async { 7 }).await);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        let refresh = tokio::spawn({
+            let cache = cache.clone();
+            async move {
+                cache
+                    .fetch(async {
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        11
+                    })
+                    .await
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), cache.clear())
+                .await
+                .is_ok(),
+            "clear waited on the fetch"
+        );
+        assert_eq!(11, refresh.await.unwrap());
+
+        // the value fetched before the clear must not be stored
+        assert_eq!(13, cache.fetch(async { 13 }).await);
+        assert_eq!(
+            13,
+            tokio::time::timeout(Duration::from_millis(50), cache.fetch(async { 17 }))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn abandoned_fetch() {
+        let cache = Cache::<i32>::const_once();
+        let slow = cache.fetch(async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            7
+        });
+
+        // the slow fetch is dropped while the waiter is waiting on it, the waiter
+        // then runs its own fetch
+        let abandon = async {
+            tokio::select! {
+                _ = slow => panic!("slow fetch completed"),
+                _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+            }
+        };
+        let waiter = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            cache.fetch(async { 11 }).await
+        };
+
+        let (value, _) = tokio::join!(
+            tokio::time::timeout(Duration::from_millis(200), waiter),
+            abandon
+        );
+        assert_eq!(11, value.unwrap());
+    }
+
+    #[tokio::test]
+    async fn stale_while_revalidate() {
+        let cache = Arc::new(Cache::<i32>::const_stale(
+            Duration::from_millis(50),
+            Duration::from_secs(60),
+            Duration::ZERO,
+        ));
+
+        assert_eq!(
+            7,
+            cache
+                .try_fetch_stale(async { Ok::<_, Infallible>(7) })
+                .await
+                .unwrap()
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // the stale value is returned without waiting on the refresh
+        let stale = tokio::time::timeout(
+            Duration::from_millis(50),
+            cache.try_fetch_stale(async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok::<_, Infallible>(11)
+            }),
+        )
+        .await;
+        assert_eq!(7, stale.unwrap().unwrap());
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(
+            11,
+            cache
+                .try_fetch_stale(async { Ok::<_, Infallible>(13) })
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_hard_ttl() {
+        let cache = Arc::new(Cache::<i32>::const_stale(
+            Duration::from_millis(10),
+            Duration::from_millis(50),
+            Duration::ZERO,
+        ));
+
+        assert_eq!(
+            7,
+            cache
+                .try_fetch_stale(async { Ok::<_, Infallible>(7) })
+                .await
+                .unwrap()
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            11,
+            cache
+                .try_fetch_stale(async { Ok::<_, Infallible>(11) })
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_grace() {
+        let cache = Arc::new(Cache::<i32>::const_stale(
+            Duration::from_millis(10),
+            Duration::from_millis(50),
+            Duration::from_millis(200),
+        ));
+
+        assert_eq!(
+            7,
+            cache
+                .try_fetch_stale(async { Ok::<_, anyhow::Error>(7) })
+                .await
+                .unwrap()
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // the value is past the hard TTL but within the grace period
+        assert_eq!(
+            7,
+            cache
+                .try_fetch_stale(async { Err::<i32, _>(anyhow!("database unavailable")) })
+                .await
+                .unwrap()
+        );
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(
+            cache
+                .try_fetch_stale(async { Err::<i32, _>(anyhow!("database unavailable")) })
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_refresh_failure() {
+        let cache = Arc::new(Cache::<i32>::const_stale(
+            Duration::from_millis(10),
+            Duration::from_secs(60),
+            Duration::ZERO,
+        ));
+
+        assert_eq!(
+            7,
+            cache
+                .try_fetch_stale(async { Ok::<_, anyhow::Error>(7) })
+                .await
+                .unwrap()
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let failed = cache
+            .try_fetch_stale(async { Err::<i32, _>(anyhow!("database unavailable")) })
+            .await;
+        assert_eq!(7, failed.unwrap());
+
+        // the failed refresh keeps the stale value
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            7,
+            cache
+                .try_fetch(async { Ok::<_, Infallible>(11) })
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_refresh_failure_waits_soft_ttl() {
+        let cache = Arc::new(Cache::<i32>::const_stale(
+            Duration::from_millis(100),
+            Duration::from_secs(60),
+            Duration::ZERO,
+        ));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let fail = || {
+            let fetches = fetches.clone();
+            async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                Err::<i32, _>(anyhow!("database unavailable"))
+            }
+        };
+
+        assert_eq!(
+            7,
+            cache
+                .try_fetch_stale(async { Ok::<_, anyhow::Error>(7) })
+                .await
+                .unwrap()
+        );
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        assert_eq!(7, cache.try_fetch_stale(fail()).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+
+        // without a backoff the failed refresh still delays the next one
+        assert_eq!(7, cache.try_fetch_stale(fail()).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(7, cache.try_fetch_stale(fail()).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(2, fetches.load(Ordering::SeqCst));
+    }
 }
@@ -0,0 +1,664 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::{Mutex, watch};
+
+use super::{CacheStats, Metrics};
+
+/// The value fetched for a key shared with the callers waiting on it, `None` when the
+/// fetch failed as the error type is chosen by each call and may not be `Clone`
+type Landed<V> = Option<V>;
+
+struct Entry<V> {
+    value: V,
+    expires: DateTime<Utc>,
+    last_used: u64,
+    tags: Vec<String>,
+}
+
+/// The entries along with the order they were last used in, the oldest use is the
+/// first entry of `order` which makes it the next one evicted.
+struct Entries<K, V> {
+    values: BTreeMap<K, Entry<V>>,
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    /// The fetches currently in progress by key, callers which miss the cache wait on
+    /// these rather than starting their own fetch
+    in_flight: BTreeMap<K, watch::Receiver<Option<Landed<V>>>>,
+    /// Incremented whenever entries are invalidated so a fetch which started before
+    /// doesn't store its (possibly stale) value
+    generation: u64,
+    /// When an entry was last stored
+    refreshed: Option<DateTime<Utc>>,
+}
+
+/// The role of a caller which missed the cache
+enum Fetch<V> {
+    /// Another caller is fetching the value for the key
+    Wait(watch::Receiver<Option<Landed<V>>>),
+    /// This caller is fetching the value and shares it through the sender
+    Lead(watch::Sender<Option<Landed<V>>>, u64),
+}
+
+impl<K, V> Entries<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    const fn new() -> Self {
+        Self {
+            values: BTreeMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            in_flight: BTreeMap::new(),
+            generation: 0,
+            refreshed: None,
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        let now = Utc::now();
+        let tick = self.next_tick();
+        let entry = self.values.get_mut(key)?;
+
+        if now >= entry.expires {
+            self.remove(key);
+            return None;
+        }
+
+        self.order.remove(&entry.last_used);
+        entry.last_used = tick;
+        self.order.insert(tick, key.clone());
+        Some(entry.value.clone())
+    }
+
+    fn insert(
+        &mut self,
+        key: K,
+        value: V,
+        expires: DateTime<Utc>,
+        tags: Vec<String>,
+        capacity: usize,
+    ) {
+        self.remove(&key);
+        if capacity == 0 {
+            return;
+        }
+
+        if self.values.len() >= capacity {
+            self.remove_expired();
+        }
+
+        while self.values.len() >= capacity {
+            let Some((_, evicted)) = self.order.pop_first() else {
+                break;
+            };
+            self.values.remove(&evicted);
+        }
+
+        let last_used = self.next_tick();
+        self.refreshed = Some(Utc::now());
+        self.order.insert(last_used, key.clone());
+        self.values.insert(
+            key,
+            Entry {
+                value,
+                expires,
+                last_used,
+                tags,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.values.remove(key)?;
+        self.order.remove(&entry.last_used);
+        Some(entry.value)
+    }
+
+    fn remove_where<P>(&mut self, predicate: P) -> usize
+    where
+        P: Fn(&K, &Entry<V>) -> bool,
+    {
+        let keys = self
+            .values
+            .iter()
+            .filter(|(key, entry)| predicate(key, entry))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<K>>();
+
+        keys.iter().filter_map(|key| self.remove(key)).count()
+    }
+
+    /// Drops the fetches in progress so their values aren't stored, the callers
+    /// already waiting on them still receive the values
+    fn discard_in_flight(&mut self) {
+        self.in_flight.clear();
+        self.generation += 1;
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.order.clear();
+        self.discard_in_flight();
+    }
+
+    fn remove_expired(&mut self) -> usize {
+        let now = Utc::now();
+        let keys = self
+            .values
+            .iter()
+            .filter(|(_, entry)| now >= entry.expires)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<K>>();
+
+        keys.iter().filter_map(|key| self.remove(key)).count()
+    }
+}
+
+///
+/// Implements a cache of values by key with a limited capacity, when the cache is full
+/// the least recently used entry is evicted. Each entry has its own TTL which defaults
+/// to the TTL of the cache. Unlike [crate::Cache] the lock is not held while fetching
+/// so a slow fetch for one key doesn't block readers of other keys.
+///
+/// Only one fetch runs at a time for each key and every caller which misses the cache
+/// for the key shares its value. When the fetch fails the waiting callers run their
+/// own. Invalidating entries while fetching discards the fetched value rather than
+/// storing a value which may predate the invalidation.
+///
+pub struct KeyedCache<K, V> {
+    capacity: usize,
+    ttl: Option<Duration>,
+    /// The tags carried by every entry in the cache
+    tags: &'static [&'static str],
+    entries: Mutex<Entries<K, V>>,
+    metrics: Metrics,
+}
+
+impl<K, V> KeyedCache<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone + Debug,
+{
+    /// Creates a cache holding at most `capacity` entries which never expire
+    pub const fn const_lru(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ttl: None,
+            tags: &[],
+            entries: Mutex::const_new(Entries::new()),
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// Creates a cache holding at most `capacity` entries each expiring after `ttl`
+    pub const fn const_ttl(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl: Some(ttl),
+            tags: &[],
+            entries: Mutex::const_new(Entries::new()),
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// Tags every entry of the cache, see [KeyedCache::invalidate_tag]
+    pub const fn with_tags(mut self, tags: &'static [&'static str]) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of entries in the cache, this may include expired entries which
+    /// haven't been accessed since they expired
+    pub async fn len(&self) -> usize {
+        self.entries.lock().await.values.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.entries.lock().await.values.is_empty()
+    }
+
+    /// The usage of the cache, the expiry is that of the next entry to expire
+    pub async fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().await;
+        CacheStats {
+            hits: self.metrics.hits.load(Ordering::Relaxed),
+            misses: self.metrics.misses.load(Ordering::Relaxed),
+            errors: self.metrics.errors.load(Ordering::Relaxed),
+            cached: !entries.values.is_empty(),
+            last_refresh: entries.refreshed,
+            expires: entries
+                .values
+                .values()
+                .map(|entry| entry.expires)
+                .filter(|expires| *expires != DateTime::<Utc>::MAX_UTC)
+                .min(),
+            failure: None,
+        }
+    }
+
+    pub async fn get(&self, key: &K) -> Option<V> {
+        self.entries.lock().await.get(key)
+    }
+
+    pub async fn insert(&self, key: K, value: V) {
+        self.store(key, value, self.ttl, &[]).await;
+    }
+
+    pub async fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        self.store(key, value, Some(ttl), &[]).await;
+    }
+
+    /// Inserts the entry carrying the tags, along with the tags of the cache
+    pub async fn insert_tagged(&self, key: K, value: V, tags: &[&str]) {
+        self.store(key, value, self.ttl, tags).await;
+    }
+
+    /// Removes the entry for the key returning true if it was present
+    pub async fn invalidate(&self, key: &K) -> bool {
+        let mut entries = self.entries.lock().await;
+        entries.discard_in_flight();
+        entries.remove(key).is_some()
+    }
+
+    /// Removes every entry matching the predicate returning the number removed
+    pub async fn invalidate_where<P>(&self, predicate: P) -> usize
+    where
+        P: Fn(&K, &V) -> bool,
+    {
+        let mut entries = self.entries.lock().await;
+        entries.discard_in_flight();
+        entries.remove_where(|key, entry| predicate(key, &entry.value))
+    }
+
+    /// Removes every entry carrying the tag, or every entry when the cache itself
+    /// carries the tag, returning true if anything was removed
+    pub async fn invalidate_tag(&self, tag: &str) -> bool {
+        let mut entries = self.entries.lock().await;
+        if self.tags.contains(&tag) {
+            let removed = !entries.values.is_empty();
+            entries.clear();
+            return removed;
+        }
+
+        // the tags of a fetch in progress aren't known until it completes
+        entries.discard_in_flight();
+        entries.remove_where(|_, entry| entry.tags.iter().any(|entry_tag| entry_tag == tag)) > 0
+    }
+
+    /// Removes every entry, a fetch already in progress still completes for the
+    /// callers waiting on it but its value is not stored
+    pub async fn clear(&self) {
+        self.entries.lock().await.clear();
+    }
+
+    pub async fn try_fetch<F, R, E>(&self, key: K, op: F) -> Result<V, E>
+    where
+        R: Into<V>,
+        F: Future<Output = Result<R, E>>,
+    {
+        self.try_fetch_with_ttl(key, self.ttl, op).await
+    }
+
+    /// Fetches the value for the key, caching it with the specified TTL rather than
+    /// the default TTL of the cache, `None` means the entry never expires
+    pub async fn try_fetch_with_ttl<F, R, E>(
+        &self,
+        key: K,
+        ttl: Option<Duration>,
+        op: F,
+    ) -> Result<V, E>
+    where
+        R: Into<V>,
+        F: Future<Output = Result<R, E>>,
+    {
+        self.fetch_entry(key, ttl, &[], op).await
+    }
+
+    /// Fetches the value for the key, caching it with the tags
+    pub async fn try_fetch_tagged<F, R, E>(&self, key: K, tags: &[&str], op: F) -> Result<V, E>
+    where
+        R: Into<V>,
+        F: Future<Output = Result<R, E>>,
+    {
+        self.fetch_entry(key, self.ttl, tags, op).await
+    }
+
+    pub async fn fetch<F, R>(&self, key: K, op: F) -> V
+    where
+        F: Future<Output = R>,
+        R: Into<V>,
+    {
+        match self
+            .try_fetch(key, async move { Ok::<R, Infallible>(op.await) })
+            .await
+        {
+            Ok(value) => value,
+            Err(error) => match error {},
+        }
+    }
+
+    /// Returns the cached value for the key, otherwise either waits on the fetch in
+    /// progress for the key or runs the fetch storing the value unless the entries were
+    /// invalidated while fetching
+    async fn fetch_entry<F, R, E>(
+        &self,
+        key: K,
+        ttl: Option<Duration>,
+        tags: &[&str],
+        op: F,
+    ) -> Result<V, E>
+    where
+        R: Into<V>,
+        F: Future<Output = Result<R, E>>,
+    {
+        let (sender, generation) = loop {
+            match self.begin_fetch(&key).await {
+                Ok(value) => return Ok(value),
+                Err(Fetch::Lead(sender, generation)) => break (sender, generation),
+                Err(Fetch::Wait(receiver)) => {
+                    if let Some(value) = Self::wait(receiver).await {
+                        return Ok(value);
+                    }
+                    // the fetch failed or the caller fetching went away, fetch our own
+                }
+            }
+        };
+
+        let result = op.await.map(Into::into);
+        if result.is_err() {
+            self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut entries = self.entries.lock().await;
+        let current = entries.generation == generation;
+        if current {
+            entries.in_flight.remove(&key);
+            if let Ok(value) = &result {
+                let expires = self.expires(ttl);
+                let tags = tags.iter().map(|tag| tag.to_string()).collect();
+                entries.insert(key, value.clone(), expires, tags, self.capacity);
+            }
+        } else {
+            tracing::debug!("discarding {key:?} which was invalidated while fetching");
+        }
+        drop(entries);
+
+        sender.send_replace(Some(result.as_ref().ok().cloned()));
+        result
+    }
+
+    /// Returns the cached value if it's still valid, otherwise either joins the fetch
+    /// in progress for the key or starts a new one
+    async fn begin_fetch(&self, key: &K) -> Result<V, Fetch<V>> {
+        let mut entries = self.entries.lock().await;
+        if let Some(value) = entries.get(key) {
+            self.metrics.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+
+        self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+        // a closed channel means the caller fetching went away before finishing
+        if let Some(receiver) = entries.in_flight.get(key)
+            && receiver.has_changed().is_ok()
+        {
+            return Err(Fetch::Wait(receiver.clone()));
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        entries.in_flight.insert(key.clone(), receiver);
+        Err(Fetch::Lead(sender, entries.generation))
+    }
+
+    /// Waits for another caller to finish fetching, `None` if the fetch failed or the
+    /// caller went away first
+    async fn wait(mut receiver: watch::Receiver<Option<Landed<V>>>) -> Landed<V> {
+        receiver
+            .wait_for(Option::is_some)
+            .await
+            .ok()
+            .and_then(|landed| landed.clone())
+            .flatten()
+    }
+
+    async fn store(&self, key: K, value: V, ttl: Option<Duration>, tags: &[&str]) {
+        let expires = self.expires(ttl);
+        let tags = tags.iter().map(|tag| tag.to_string()).collect();
+        self.entries
+            .lock()
+            .await
+            .insert(key, value, expires, tags, self.capacity);
+    }
+
+    fn expires(&self, ttl: Option<Duration>) -> DateTime<Utc> {
+        ttl.and_then(|ttl| Utc::now().checked_add_signed(chrono::Duration::from_std(ttl).ok()?))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+    use tokio::sync::oneshot;
+
+    use super::KeyedCache;
+
+    #[tokio::test]
+    async fn fetch_once_per_key() {
+        let cache = KeyedCache::<i32, String>::const_lru(4);
+
+        assert_eq!("one", cache.fetch(1, async { "one" }).await);
+        assert_eq!("one", cache.fetch(1, async { "other" }).await);
+        assert_eq!("two", cache.fetch(2, async { "two" }).await);
+        assert_eq!(2, cache.len().await);
+    }
+
+    #[tokio::test]
+    async fn evict_least_recently_used() {
+        let cache = KeyedCache::<i32, i32>::const_lru(2);
+        cache.insert(1, 1).await;
+        cache.insert(2, 2).await;
+
+        // touch 1 so 2 becomes the least recently used
+        assert_eq!(Some(1), cache.get(&1).await);
+        cache.insert(3, 3).await;
+
+        assert_eq!(Some(1), cache.get(&1).await);
+        assert_eq!(None, cache.get(&2).await);
+        assert_eq!(Some(3), cache.get(&3).await);
+        assert_eq!(2, cache.len().await);
+    }
+
+    #[tokio::test]
+    async fn replace_does_not_evict() {
+        let cache = KeyedCache::<i32, i32>::const_lru(2);
+        cache.insert(1, 1).await;
+        cache.insert(2, 2).await;
+        cache.insert(2, 7).await;
+
+        assert_eq!(Some(1), cache.get(&1).await);
+        assert_eq!(Some(7), cache.get(&2).await);
+    }
+
+    #[tokio::test]
+    async fn entry_ttl() {
+        let cache = KeyedCache::<i32, i32>::const_ttl(4, Duration::from_secs(60));
+        cache.insert(1, 1).await;
+        cache.insert_with_ttl(2, 2, Duration::from_millis(50)).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(Some(1), cache.get(&1).await);
+        assert_eq!(None, cache.get(&2).await);
+        assert_eq!(1, cache.len().await);
+    }
+
+    #[tokio::test]
+    async fn evict_expired_before_used() {
+        let cache = KeyedCache::<i32, i32>::const_lru(2);
+        cache.insert(1, 1).await;
+        cache.insert_with_ttl(2, 2, Duration::from_millis(10)).await;
+        assert_eq!(Some(2), cache.get(&2).await);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        cache.insert(3, 3).await;
+
+        assert_eq!(Some(1), cache.get(&1).await);
+        assert_eq!(Some(3), cache.get(&3).await);
+    }
+
+    #[tokio::test]
+    async fn try_fetch_error_not_cached() {
+        let cache = KeyedCache::<i32, String>::const_lru(2);
+
+        let result = cache
+            .try_fetch(1, async { Err::<&str, _>(anyhow!("err")) })
+            .await;
+        assert!(result.is_err());
+        assert!(cache.is_empty().await);
+
+        let result = cache
+            .try_fetch(1, async { Ok::<_, anyhow::Error>("value") })
+            .await;
+        assert_eq!("value", result.unwrap());
+    }
+
+    #[tokio::test]
+    async fn invalidate() {
+        let cache = KeyedCache::<i32, i32>::const_lru(4);
+        cache.insert(1, 1).await;
+        cache.insert(2, 2).await;
+
+        assert!(cache.invalidate(&1).await);
+        assert!(!cache.invalidate(&1).await);
+        assert_eq!(None, cache.get(&1).await);
+        assert_eq!(Some(2), cache.get(&2).await);
+    }
+
+    #[tokio::test]
+    async fn invalidate_where() {
+        static CACHE: KeyedCache<i32, i32> = KeyedCache::const_lru(8);
+        for key in 0..6 {
+            CACHE.insert(key, key * 10).await;
+        }
+
+        assert_eq!(3, CACHE.invalidate_where(|key, _| key % 2 == 0).await);
+        assert_eq!(1, CACHE.invalidate_where(|_, value| *value == 10).await);
+        assert_eq!(2, CACHE.len().await);
+        assert_eq!(Some(30), CACHE.get(&3).await);
+        assert_eq!(Some(50), CACHE.get(&5).await);
+    }
+
+    #[tokio::test]
+    async fn invalidate_tag() {
+        let cache = KeyedCache::<i32, i32>::const_lru(8);
+        cache.insert_tagged(1, 1, &["news"]).await;
+        cache.insert_tagged(2, 2, &["news", "home"]).await;
+        cache.insert(3, 3).await;
+        assert_eq!(
+            4,
+            cache
+                .try_fetch_tagged(4, &["home"], async { Ok::<_, anyhow::Error>(4) })
+                .await
+                .unwrap()
+        );
+
+        assert!(cache.invalidate_tag("news").await);
+        assert!(!cache.invalidate_tag("news").await);
+        assert_eq!(None, cache.get(&1).await);
+        assert_eq!(None, cache.get(&2).await);
+        assert_eq!(Some(3), cache.get(&3).await);
+        assert_eq!(Some(4), cache.get(&4).await);
+    }
+
+    #[tokio::test]
+    async fn invalidate_cache_tag() {
+        static CACHE: KeyedCache<i32, i32> = KeyedCache::const_lru(8).with_tags(&["news"]);
+        CACHE.insert(1, 1).await;
+        CACHE.insert_tagged(2, 2, &["home"]).await;
+
+        assert!(CACHE.invalidate_tag("news").await);
+        assert!(CACHE.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let cache = KeyedCache::<i32, i32>::const_ttl(4, Duration::from_secs(60));
+        assert_eq!(1, cache.fetch(1, async { 1 }).await);
+        assert_eq!(1, cache.fetch(1, async { 11 }).await);
+        assert!(
+            cache
+                .try_fetch(2, async { Err::<i32, _>(anyhow!("err")) })
+                .await
+                .is_err()
+        );
+
+        let stats = cache.stats().await;
+        assert_eq!(1, stats.hits);
+        assert_eq!(2, stats.misses);
+        assert_eq!(1, stats.errors);
+        assert!(stats.cached);
+        assert!(stats.last_refresh.is_some());
+        assert!(stats.expires.is_some());
+
+        cache.clear().await;
+        assert!(!cache.stats().await.cached);
+    }
+
+    #[tokio::test]
+    async fn fetch_once_while_fetching() {
+        static CACHE: KeyedCache<i32, i32> = KeyedCache::const_lru(4);
+        static FETCHES: AtomicUsize = AtomicUsize::new(0);
+
+        let fetches = (0..4).map(|_| {
+            tokio::spawn(CACHE.fetch(1, async {
+                FETCHES.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                7
+            }))
+        });
+        for fetch in fetches.collect::<Vec<_>>() {
+            assert_eq!(7, fetch.await.unwrap());
+        }
+        assert_eq!(1, FETCHES.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn invalidate_while_fetching() {
+        static CACHE: KeyedCache<i32, i32> = KeyedCache::const_lru(4);
+        let (started, fetching) = oneshot::channel();
+        let (finish, finished) = oneshot::channel::<()>();
+
+        let fetch = tokio::spawn(CACHE.try_fetch_tagged(1, &["news"], async move {
+            started.send(()).unwrap();
+            finished.await.unwrap();
+            Ok::<_, anyhow::Error>(7)
+        }));
+        fetching.await.unwrap();
+        CACHE.invalidate_tag("news").await;
+        finish.send(()).unwrap();
+
+        // the caller still receives the value it fetched but it isn't cached
+        assert_eq!(7, fetch.await.unwrap().unwrap());
+        assert_eq!(None, CACHE.get(&1).await);
+        assert_eq!(11, CACHE.fetch(1, async { 11 }).await);
+    }
+}
@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Request, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Serialize;
+
+use super::{Cache, CacheFailure, KeyedCache, ResponseCache};
+use crate::html::escape;
+use crate::invalidation::{Invalidate, InvalidationBus};
+use crate::page::PageFormat;
+
+/// Decides whether the request may use the cache admin endpoints, anything the
+/// decision needs is taken from the request before the future is returned
+pub type AuthorizeFn = Arc<dyn Fn(&Parts) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// A snapshot of how a cache has been used since the application started
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct CacheStats {
+    /// Callers served from the cache, including stale values
+    pub hits: u64,
+    /// Callers which had to wait on or run a fetch
+    pub misses: u64,
+    /// Fetches which failed
+    pub errors: u64,
+    /// True when the cache holds a value, it may have expired
+    pub cached: bool,
+    pub last_refresh: Option<DateTime<Utc>>,
+    /// When the cached value expires, `None` when there's no value or it never expires
+    pub expires: Option<DateTime<Utc>>,
+    /// Set while the cache is backing off after failed fetches
+    pub failure: Option<CacheFailure>,
+}
+
+/// A cache which can report its stats to the [CacheRegistry]
+pub trait Inspect: Invalidate {
+    fn stats(&self) -> BoxFuture<'_, CacheStats>;
+}
+
+impl<T> Inspect for Cache<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    fn stats(&self) -> BoxFuture<'_, CacheStats> {
+        Cache::stats(self).boxed()
+    }
+}
+
+impl<K, V> Inspect for KeyedCache<K, V>
+where
+    K: Ord + Clone + Debug + Send + Sync,
+    V: Clone + Debug + Send + Sync,
+{
+    fn stats(&self) -> BoxFuture<'_, CacheStats> {
+        KeyedCache::stats(self).boxed()
+    }
+}
+
+impl Inspect for ResponseCache {
+    fn stats(&self) -> BoxFuture<'_, CacheStats> {
+        ResponseCache::stats(self).boxed()
+    }
+}
+
+///
+/// Names the caches of the application so they can be inspected and cleared from
+/// the admin endpoints, see [cache_routes]. Registering a cache is optional, an
+/// unregistered cache works exactly the same it just isn't listed.
+///
+/// With an [InvalidationBus] the registered caches are also registered with the bus
+/// and clearing one clears it on every instance.
+///
+#[derive(Default)]
+pub struct CacheRegistry {
+    caches: RwLock<BTreeMap<String, &'static dyn Inspect>>,
+    invalidation: Option<Arc<InvalidationBus>>,
+}
+
+impl CacheRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clears the caches through the bus so every instance drops its copy
+    pub fn with_invalidation(invalidation: Arc<InvalidationBus>) -> Self {
+        Self {
+            invalidation: Some(invalidation),
+            ..Default::default()
+        }
+    }
+
+    pub fn register<N>(&self, name: N, cache: &'static dyn Inspect)
+    where
+        N: Into<String>,
+    {
+        let name = name.into();
+        tracing::debug!("registering cache '{name}'");
+        if let Some(invalidation) = &self.invalidation {
+            invalidation.register(name.clone(), cache);
+        }
+        if let Ok(mut caches) = self.caches.write() {
+            caches.insert(name, cache);
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.caches
+            .read()
+            .map(|caches| caches.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The stats of every registered cache by name
+    pub async fn stats(&self) -> BTreeMap<String, CacheStats> {
+        let mut stats = BTreeMap::new();
+        for (name, cache) in self.caches() {
+            stats.insert(name, cache.stats().await);
+        }
+        stats
+    }
+
+    /// Clears the named cache, on every instance when there's an [InvalidationBus],
+    /// returns false if no cache is registered with the name
+    pub async fn clear(&self, name: &str) -> bool {
+        let cache = self
+            .caches
+            .read()
+            .ok()
+            .and_then(|caches| caches.get(name).copied());
+
+        let Some(cache) = cache else {
+            return false;
+        };
+
+        tracing::info!("clearing cache '{name}'");
+        match &self.invalidation {
+            // the bus clears the local copy even when the other instances can't be told
+            Some(invalidation) => {
+                if let Err(e) = invalidation.invalidate(name).await {
+                    tracing::error!(error = ?e, "failed to clear cache '{name}' on the other instances: {e}");
+                }
+            }
+            None => cache.invalidate().await,
+        }
+        true
+    }
+
+    /// Clears everything carrying the tag from the registered caches on this instance,
+    /// returns false if nothing carries the tag
+    pub async fn invalidate_tag(&self, tag: &str) -> bool {
+        let mut cleared = false;
+        for (name, cache) in self.caches() {
+            if cache.invalidate_tag(tag).await {
+                tracing::info!("cleared '{tag}' from cache '{name}'");
+                cleared = true;
+            }
+        }
+        cleared
+    }
+
+    fn caches(&self) -> Vec<(String, &'static dyn Inspect)> {
+        self.caches
+            .read()
+            .map(|caches| {
+                caches
+                    .iter()
+                    .map(|(name, cache)| (name.clone(), *cache))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Creates the cache admin endpoints, the stats of the registered caches at
+/// `/admin/caches` and `POST /admin/caches/{name}/clear` to clear one. Browsers are sent
+/// a minimal page listing the caches with a button clearing each, anything else the
+/// stats as JSON. Requests the function doesn't authorize are forbidden.
+pub fn cache_routes(registry: Arc<CacheRegistry>, authorize: AuthorizeFn) -> Router {
+    Router::new()
+        .route("/admin/caches", get(list_caches))
+        .route("/admin/caches/{name}/clear", post(clear_cache))
+        .layer(Extension(registry))
+        .layer(middleware::from_fn_with_state(
+            authorize,
+            require_authorization,
+        ))
+}
+
+async fn require_authorization(
+    State(authorize): State<AuthorizeFn>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    if !authorize(&parts).await {
+        tracing::warn!("refusing unauthorized request for {}", parts.uri);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
+
+/// Only browsers asking for html are sent the page, a request without an `Accept`
+/// header gets JSON
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+        && PageFormat::negotiate(headers) == PageFormat::Html
+}
+
+async fn list_caches(
+    Extension(registry): Extension<Arc<CacheRegistry>>,
+    headers: HeaderMap,
+) -> Response {
+    let stats = registry.stats().await;
+    let response = if accepts_html(&headers) {
+        Html(caches_page(&stats)).into_response()
+    } else {
+        Json(stats).into_response()
+    };
+    ([(header::VARY, header::ACCEPT.as_str())], response).into_response()
+}
+
+fn caches_page(stats: &BTreeMap<String, CacheStats>) -> String {
+    let time = |time: Option<DateTime<Utc>>| {
+        time.map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default()
+    };
+
+    let rows = stats
+        .iter()
+        .map(|(name, stats)| {
+            let failure = stats
+                .failure
+                .as_ref()
+                .map(|failure| {
+                    format!(
+                        "{} failures, retrying at {}",
+                        failure.failures,
+                        time(Some(failure.retry_at))
+                    )
+                })
+                .unwrap_or_default();
+            let clear = format!(
+                "/admin/caches/{}/clear",
+                utf8_percent_encode(name, NON_ALPHANUMERIC)
+            );
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+                 <td>{}</td><td>{failure}</td><td><form method=\"post\" action=\"{clear}\">\
+                 <button>Clear</button></form></td></tr>",
+                escape(name),
+                stats.hits,
+                stats.misses,
+                stats.errors,
+                if stats.cached { "yes" } else { "no" },
+                time(stats.last_refresh),
+                time(stats.expires),
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Caches</title></head>\
+         <body><h1>Caches</h1><table><thead><tr><th>Name</th><th>Hits</th><th>Misses</th>\
+         <th>Errors</th><th>Cached</th><th>Last refresh</th><th>Expires</th>\
+         <th>Failure</th><th></th></tr></thead><tbody>{rows}</tbody></table></body></html>"
+    )
+}
+
+async fn clear_cache(
+    Extension(registry): Extension<Arc<CacheRegistry>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if registry.clear(&name).await {
+        // the form of the page posts here, the browser is sent back to the list
+        if accepts_html(&headers) {
+            Redirect::to("/admin/caches").into_response()
+        } else {
+            StatusCode::NO_CONTENT.into_response()
+        }
+    } else {
+        tracing::warn!("unable to clear unknown cache '{name}'");
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    use super::*;
+    use anyhow::anyhow;
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use pretty_assertions::assert_eq;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn cache_stats() {
+        let cache = Cache::<i32>::const_ttl(Duration::from_secs(60));
+        assert_eq!(7, cache.fetch(async { 7 }).await);
+        assert_eq!(7, cache.fetch(async { 11 }).await);
+        cache.clear().await;
+        assert!(
+            cache
+                .try_fetch(async { Err::<i32, _>(anyhow!("database unavailable")) })
+                .await
+                .is_err()
+        );
+
+        let stats = cache.stats().await;
+        assert_eq!(1, stats.hits);
+        assert_eq!(2, stats.misses);
+        assert_eq!(1, stats.errors);
+        assert!(!stats.cached);
+        assert!(stats.last_refresh.is_some());
+        assert_eq!(None, stats.expires);
+
+        assert_eq!(
+            13,
+            cache
+                .try_fetch(async { Ok::<_, Infallible>(13) })
+                .await
+                .unwrap()
+        );
+        let stats = cache.stats().await;
+        assert!(stats.cached);
+        assert!(stats.expires.is_some_and(|expires| expires > Utc::now()));
+    }
+
+    #[tokio::test]
+    async fn never_expires() {
+        let cache = Cache::<i32>::const_once();
+        cache.fetch(async { 7 }).await;
+
+        let stats = cache.stats().await;
+        assert!(stats.cached);
+        assert_eq!(None, stats.expires);
+    }
+
+    #[tokio::test]
+    async fn list_and_clear() {
+        static NUMBERS: Cache<i32> = Cache::const_once();
+        static NAMES: Cache<String> = Cache::const_once();
+        static SQUARES: KeyedCache<i32, i32> = KeyedCache::const_lru(4);
+        static RESPONSES: ResponseCache = ResponseCache::const_ttl(4, Duration::from_secs(60));
+
+        let registry = Arc::new(CacheRegistry::new());
+        registry.register("numbers", &NUMBERS);
+        registry.register("names", &NAMES);
+        registry.register("squares", &SQUARES);
+        registry.register("responses", &RESPONSES);
+        NUMBERS.fetch(async { 7 }).await;
+        SQUARES.fetch(3, async { 9 }).await;
+        let authorize: AuthorizeFn = Arc::new(|parts: &Parts| {
+            let admin = parts.headers.get("x-admin").is_some();
+            async move { admin }.boxed()
+        });
+
+        let response = cache_routes(registry.clone(), authorize.clone())
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/caches")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+
+        let response = cache_routes(registry.clone(), authorize.clone())
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/caches")
+                    .header("x-admin", "true")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(Value::Bool(false), body["names"]["cached"]);
+        assert_eq!(Value::Bool(true), body["numbers"]["cached"]);
+        assert_eq!(Value::from(1), body["numbers"]["misses"]);
+        assert_eq!(Value::Bool(true), body["squares"]["cached"]);
+        assert_eq!(Value::from(1), body["squares"]["misses"]);
+        assert_eq!(Value::Bool(false), body["responses"]["cached"]);
+
+        let clear = |name: &str| {
+            cache_routes(registry.clone(), authorize.clone()).oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/admin/caches/{name}/clear"))
+                    .header("x-admin", "true")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        assert_eq!(
+            StatusCode::NO_CONTENT,
+            clear("numbers").await.unwrap().status()
+        );
+        assert_eq!(
+            StatusCode::NOT_FOUND,
+            clear("other").await.unwrap().status()
+        );
+        assert_eq!(11, NUMBERS.fetch(async { 11 }).await);
+    }
+
+    #[tokio::test]
+    async fn admin_page() {
+        static NUMBERS: Cache<i32> = Cache::const_once();
+        static NAMES: Cache<String> = Cache::const_once();
+
+        let registry = Arc::new(CacheRegistry::new());
+        registry.register("numbers", &NUMBERS);
+        registry.register("<names>", &NAMES);
+        NUMBERS.fetch(async { 7 }).await;
+        let authorize: AuthorizeFn = Arc::new(|parts: &Parts| {
+            let admin = parts.headers.get("x-admin").is_some();
+            async move { admin }.boxed()
+        });
+        let html = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+        let response = cache_routes(registry.clone(), authorize.clone())
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/caches")
+                    .header("accept", html)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+
+        let response = cache_routes(registry.clone(), authorize.clone())
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/caches")
+                    .header("accept", html)
+                    .header("x-admin", "true")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(
+            response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("<td>numbers</td><td>0</td><td>1</td><td>0</td><td>yes</td>"));
+        assert!(body.contains(r#"<form method="post" action="/admin/caches/numbers/clear">"#));
+        assert!(body.contains("<td>&lt;names&gt;</td>"));
+        assert!(body.contains(r#"action="/admin/caches/%3Cnames%3E/clear""#));
+
+        let response = cache_routes(registry.clone(), authorize.clone())
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/admin/caches/numbers/clear")
+                    .header("accept", html)
+                    .header("x-admin", "true")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::SEE_OTHER, response.status());
+        assert_eq!("/admin/caches", response.headers()[header::LOCATION]);
+        assert_eq!(11, NUMBERS.fetch(async { 11 }).await);
+    }
+}
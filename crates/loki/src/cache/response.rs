@@ -0,0 +1,502 @@
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    body::{Body, Bytes, to_bytes},
+    http::{HeaderMap, HeaderValue, Method, Request, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+
+use super::{CacheStats, KeyedCache};
+use crate::i18n::Locale;
+use crate::invalidation::Invalidate;
+use crate::page::PageFormat;
+
+/// A rendered response along with the validators sent to the client
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    etag: HeaderValue,
+    last_modified: SystemTime,
+}
+
+impl CachedResponse {
+    fn new(status: StatusCode, mut headers: HeaderMap, body: Bytes) -> Self {
+        let digest = Sha256::digest(&body);
+        let etag = digest[..16]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        // weak as the compression layer may encode the body differently per client
+        let etag = HeaderValue::from_str(&format!("W/\"{etag}\""))
+            .expect("a hex digest is a valid header value");
+
+        // content length is recalculated from the buffered body
+        headers.remove(header::CONTENT_LENGTH);
+        Self {
+            status,
+            headers,
+            body,
+            etag,
+            last_modified: SystemTime::now(),
+        }
+    }
+
+    /// True when the client already has this response, `If-None-Match` takes
+    /// precedence over `If-Modified-Since` when both are sent
+    fn not_modified(&self, request: &HeaderMap) -> bool {
+        if let Some(if_none_match) = request.get(header::IF_NONE_MATCH) {
+            let etag = self.etag.as_bytes();
+            let etag = etag.strip_prefix(b"W/").unwrap_or(etag);
+            return if_none_match.to_str().is_ok_and(|tags| {
+                tags.split(',')
+                    .map(str::trim)
+                    .any(|tag| tag == "*" || tag.trim_start_matches("W/").as_bytes() == etag)
+            });
+        }
+
+        request
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|since| since.to_str().ok())
+            .and_then(|since| httpdate::parse_http_date(since).ok())
+            // http dates only have second precision
+            .is_some_and(|since| since + Duration::from_secs(1) > self.last_modified)
+    }
+
+    fn respond(&self, request: &HeaderMap, cache_control: &HeaderValue) -> Response {
+        let mut response = if self.not_modified(request) {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            let mut response = (self.status, self.body.clone()).into_response();
+            response.headers_mut().extend(self.headers.clone());
+            response
+        };
+
+        let headers = response.headers_mut();
+        headers.insert(header::ETAG, self.etag.clone());
+        headers.insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&httpdate::fmt_http_date(self.last_modified))
+                .expect("an http date is a valid header value"),
+        );
+        headers
+            .entry(header::CACHE_CONTROL)
+            .or_insert_with(|| cache_control.clone());
+        response
+    }
+}
+
+///
+/// Caches rendered responses by URI so a page is only rendered again once it expires
+/// or is invalidated. Each response gets a weak ETag and a Last-Modified date so
+/// clients revalidating with `If-None-Match` or `If-Modified-Since` receive a 304.
+/// Requests which miss the cache at the same time share one render, and a render
+/// which finishes after the cache is invalidated is served but not stored.
+///
+/// Only successful responses to `GET` requests are cached, responses which set a
+/// cookie or are marked `private` or `no-store` are passed through. The cache is
+/// meant to be a static, tagging it and registering it with the
+/// [crate::InvalidationBus] clears it whenever the underlying data changes.
+///
+/// ```ignore
+/// static NEWS_RESPONSES: ResponseCache =
+///     ResponseCache::const_ttl(64, Duration::from_secs(300)).with_tags(&["news"]);
+///
+/// Router::new().route("/news", get(news)).layer(NEWS_RESPONSES.layer());
+/// ```
+///
+pub struct ResponseCache {
+    responses: KeyedCache<String, CachedResponse>,
+    max_age: Duration,
+    /// The tags carried by every cached response
+    tags: &'static [&'static str],
+}
+
+impl ResponseCache {
+    /// Creates a cache holding at most `capacity` responses each rendered again after
+    /// `ttl`, clients are told to revalidate on every request
+    pub const fn const_ttl(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            responses: KeyedCache::const_ttl(capacity, ttl),
+            max_age: Duration::ZERO,
+            tags: &[],
+        }
+    }
+
+    /// Allows clients to reuse a response for `max_age` without revalidating it
+    pub const fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Tags the cached responses, see [crate::InvalidationBus::invalidate_tag]
+    pub const fn with_tags(mut self, tags: &'static [&'static str]) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn layer(&'static self) -> ResponseCacheLayer {
+        ResponseCacheLayer { cache: self }
+    }
+
+    pub async fn clear(&self) {
+        self.responses.clear().await;
+    }
+
+    /// The usage of the cache, each uri the cache responds to is counted separately
+    pub async fn stats(&self) -> CacheStats {
+        self.responses.stats().await
+    }
+
+    /// Clears the cached responses if the cache carries the tag, returning true when
+    /// it does
+    pub async fn invalidate_tag(&self, tag: &str) -> bool {
+        if !self.tags.contains(&tag) {
+            return false;
+        }
+
+        self.clear().await;
+        true
+    }
+
+    fn cache_control(&self) -> HeaderValue {
+        if self.max_age.is_zero() {
+            HeaderValue::from_static("no-cache")
+        } else {
+            HeaderValue::from_str(&format!("public, max-age={}", self.max_age.as_secs()))
+                .expect("max-age is a valid header value")
+        }
+    }
+
+    /// True when the response may be shared with other clients
+    fn cacheable(response: &Response) -> bool {
+        let headers = response.headers();
+        let private = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| matches!(directive.trim(), "private" | "no-store"));
+
+        response.status() == StatusCode::OK && !private && !headers.contains_key(header::SET_COOKIE)
+    }
+}
+
+impl Invalidate for ResponseCache {
+    fn invalidate(&self) -> BoxFuture<'_, ()> {
+        self.clear().boxed()
+    }
+
+    fn invalidate_tag<'a>(&'a self, tag: &'a str) -> BoxFuture<'a, bool> {
+        ResponseCache::invalidate_tag(self, tag).boxed()
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseCacheLayer {
+    cache: &'static ResponseCache,
+}
+
+impl<S> Layer<S> for ResponseCacheLayer {
+    type Service = ResponseCacheService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ResponseCacheService {
+            cache: self.cache,
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseCacheService<S> {
+    cache: &'static ResponseCache,
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for ResponseCacheService<S>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // the clone may not be ready, swap it for the one polled ready
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let cache = self.cache;
+
+        if request.method() != Method::GET {
+            return inner.call(request).boxed();
+        }
+
+        async move {
+            // pages respond to the same uri in each format and locale they negotiate
+            let mut key = match PageFormat::negotiate(request.headers()) {
+                PageFormat::Html => request.uri().to_string(),
+                format => format!("{}#{format:?}", request.uri()),
+            };
+            if let Some(locale) = request.extensions().get::<Locale>() {
+                key = format!("{key}@{}", locale.as_str());
+            }
+            let headers = request.headers().clone();
+            let cache_control = cache.cache_control();
+
+            // the uncached response is the error so it's passed through without being
+            // stored, a render which finishes after the cache is invalidated isn't stored
+            let rendered = cache
+                .responses
+                .try_fetch(key.clone(), async move {
+                    let response = match inner.call(request).await {
+                        Ok(response) => response,
+                        Err(error) => match error {},
+                    };
+                    if !ResponseCache::cacheable(&response) {
+                        return Err(response);
+                    }
+
+                    let (parts, body) = response.into_parts();
+                    match to_bytes(body, usize::MAX).await {
+                        Ok(body) => Ok(CachedResponse::new(parts.status, parts.headers, body)),
+                        Err(e) => {
+                            tracing::error!("failed to buffer the response for '{key}': {e}");
+                            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+                        }
+                    }
+                })
+                .await;
+
+            Ok(match rendered {
+                Ok(cached) => cached.respond(&headers, &cache_control),
+                Err(response) => response,
+            })
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use axum::{Router, routing::get};
+    use pretty_assertions::assert_eq;
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    async fn request(router: &Router, headers: &[(header::HeaderName, &str)]) -> Response {
+        let mut request = Request::builder().uri("/page");
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+
+        router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn render_once() {
+        static CACHE: ResponseCache = ResponseCache::const_ttl(8, Duration::from_secs(60));
+        static RENDERS: AtomicUsize = AtomicUsize::new(0);
+
+        let router = Router::new()
+            .route(
+                "/page",
+                get(|| async { format!("render {}", RENDERS.fetch_add(1, Ordering::SeqCst)) }),
+            )
+            .layer(CACHE.layer());
+
+        let first = request(&router, &[]).await;
+        assert_eq!(StatusCode::OK, first.status());
+        assert_eq!("no-cache", first.headers()[header::CACHE_CONTROL]);
+        let etag = first.headers()[header::ETAG].clone();
+        assert_eq!("render 0", body(first).await);
+
+        let second = request(&router, &[]).await;
+        assert_eq!(etag, second.headers()[header::ETAG]);
+        assert_eq!("render 0", body(second).await);
+
+        CACHE.clear().await;
+        assert_eq!("render 1", body(request(&router, &[]).await).await);
+    }
+
+    #[tokio::test]
+    async fn if_none_match() {
+        static CACHE: ResponseCache = ResponseCache::const_ttl(8, Duration::from_secs(60))
+            .with_max_age(Duration::from_secs(30));
+
+        let router = Router::new()
+            .route("/page", get(|| async { "page" }))
+            .layer(CACHE.layer());
+
+        let response = request(&router, &[]).await;
+        assert_eq!(
+            "public, max-age=30",
+            response.headers()[header::CACHE_CONTROL]
+        );
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = request(&router, &[(header::IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+        assert_eq!(etag, response.headers()[header::ETAG]);
+        assert_eq!("", body(response).await);
+
+        assert!(etag.starts_with("W/\""), "{etag}");
+        let strong = format!("\"other\", {}", etag.trim_start_matches("W/"));
+        let response = request(&router, &[(header::IF_NONE_MATCH, &strong)]).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+
+        let response = request(&router, &[(header::IF_NONE_MATCH, "\"other\"")]).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("page", body(response).await);
+    }
+
+    #[tokio::test]
+    async fn if_modified_since() {
+        static CACHE: ResponseCache = ResponseCache::const_ttl(8, Duration::from_secs(60));
+
+        let router = Router::new()
+            .route("/page", get(|| async { "page" }))
+            .layer(CACHE.layer());
+
+        let response = request(&router, &[]).await;
+        let last_modified = response.headers()[header::LAST_MODIFIED]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = request(&router, &[(header::IF_MODIFIED_SINCE, &last_modified)]).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+
+        let response = request(
+            &router,
+            &[(header::IF_MODIFIED_SINCE, "Mon, 01 Jan 2001 00:00:00 GMT")],
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[tokio::test]
+    async fn skip_uncacheable() {
+        static CACHE: ResponseCache = ResponseCache::const_ttl(8, Duration::from_secs(60));
+        static RENDERS: AtomicUsize = AtomicUsize::new(0);
+
+        let router = Router::new()
+            .route(
+                "/page",
+                get(|| async {
+                    RENDERS.fetch_add(1, Ordering::SeqCst);
+                    ([(header::CACHE_CONTROL, "private")], "page")
+                }),
+            )
+            .layer(CACHE.layer());
+
+        request(&router, &[]).await;
+        let response = request(&router, &[]).await;
+        assert_eq!(None, response.headers().get(header::ETAG));
+        assert_eq!(2, RENDERS.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn cache_per_format() {
+        static CACHE: ResponseCache = ResponseCache::const_ttl(8, Duration::from_secs(60));
+
+        let router = Router::new()
+            .route(
+                "/page",
+                get(|headers: HeaderMap| async move {
+                    format!("{:?}", PageFormat::negotiate(&headers))
+                }),
+            )
+            .layer(CACHE.layer());
+
+        assert_eq!("Html", body(request(&router, &[]).await).await);
+        let json = [(header::ACCEPT, "application/json")];
+        assert_eq!("Json", body(request(&router, &json).await).await);
+        let fragment = [(header::HeaderName::from_static("hx-request"), "true")];
+        assert_eq!("Fragment", body(request(&router, &fragment).await).await);
+        assert_eq!("Html", body(request(&router, &[]).await).await);
+    }
+
+    #[tokio::test]
+    async fn invalidate_tag() {
+        static CACHE: ResponseCache =
+            ResponseCache::const_ttl(8, Duration::from_secs(60)).with_tags(&["news"]);
+        static RENDERS: AtomicUsize = AtomicUsize::new(0);
+
+        let router = Router::new()
+            .route(
+                "/page",
+                get(|| async { format!("render {}", RENDERS.fetch_add(1, Ordering::SeqCst)) }),
+            )
+            .layer(CACHE.layer());
+
+        assert_eq!("render 0", body(request(&router, &[]).await).await);
+        assert!(!CACHE.invalidate_tag("events").await);
+        assert_eq!("render 0", body(request(&router, &[]).await).await);
+        assert!(CACHE.invalidate_tag("news").await);
+        assert_eq!("render 1", body(request(&router, &[]).await).await);
+    }
+
+    #[tokio::test]
+    async fn invalidate_tag_while_rendering() {
+        static CACHE: ResponseCache =
+            ResponseCache::const_ttl(8, Duration::from_secs(60)).with_tags(&["news"]);
+        static RENDERS: AtomicUsize = AtomicUsize::new(0);
+        static RENDERING: Notify = Notify::const_new();
+        static FINISH: Notify = Notify::const_new();
+
+        let router = Router::new()
+            .route(
+                "/page",
+                get(|| async {
+                    let render = RENDERS.fetch_add(1, Ordering::SeqCst);
+                    if render == 0 {
+                        RENDERING.notify_one();
+                        FINISH.notified().await;
+                    }
+                    format!("render {render}")
+                }),
+            )
+            .layer(CACHE.layer());
+
+        let first = tokio::spawn({
+            let router = router.clone();
+            async move { body(request(&router, &[]).await).await }
+        });
+        RENDERING.notified().await;
+        assert!(CACHE.invalidate_tag("news").await);
+        FINISH.notify_one();
+
+        // the render which started before the invalidation isn't served again
+        assert_eq!("render 0", first.await.unwrap());
+        assert_eq!("render 1", body(request(&router, &[]).await).await);
+        assert_eq!("render 1", body(request(&router, &[]).await).await);
+    }
+}
@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::{Value, json};
+
+use crate::{
+    i18n::{self, Locale},
+    page::{Page, PageFormat},
+    registry::Registry,
+    reload::Reloadable,
+};
+
+///
+/// Describes an error response for its error page, handlers return it with the status
+/// `(StatusCode::NOT_FOUND, ErrorDetails::message("The article was removed"))`. The
+/// message is shown to visitors while the details are only shown in debug builds.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ErrorDetails {
+    message: Option<String>,
+    details: Option<String>,
+}
+
+impl ErrorDetails {
+    pub fn message<M>(message: M) -> Self
+    where
+        M: Into<String>,
+    {
+        Self {
+            message: Some(message.into()),
+            details: None,
+        }
+    }
+
+    pub fn details<D>(details: D) -> Self
+    where
+        D: Into<String>,
+    {
+        Self {
+            message: None,
+            details: Some(details.into()),
+        }
+    }
+
+    pub fn with_details<D>(mut self, details: D) -> Self
+    where
+        D: Into<String>,
+    {
+        self.details = Some(details.into());
+        self
+    }
+}
+
+/// Without the error pages the message is sent as text, as it was before templates
+impl IntoResponse for ErrorDetails {
+    fn into_response(self) -> Response {
+        let mut response = match &self.message {
+            Some(message) => {
+                ([(header::CONTENT_TYPE, "text/plain")], message.clone()).into_response()
+            }
+            None => ().into_response(),
+        };
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+///
+/// Renders the error responses of requests from browsers with the `errors/{status}`
+/// template, or `errors/error` when the status has no template of its own. Responses
+/// with a body of their own are left alone unless they carry [ErrorDetails], so JSON
+/// errors from an api pass through.
+///
+pub(crate) struct ErrorPages {
+    registry: Reloadable<Registry>,
+    layout: Option<String>,
+}
+
+impl ErrorPages {
+    pub(crate) fn new(registry: Reloadable<Registry>, layout: Option<String>) -> Self {
+        Self { registry, layout }
+    }
+
+    fn render(
+        &self,
+        status: StatusCode,
+        details: ErrorDetails,
+        locale: Option<String>,
+    ) -> Option<String> {
+        let registry = self.registry.get();
+        let template = registry.error_template(status)?;
+
+        let reason = status.canonical_reason().unwrap_or("Error");
+        let mut values = json!({
+            "status": status.as_u16(),
+            "reason": reason,
+            "message": details.message.unwrap_or_else(|| reason.to_owned()),
+        });
+        if cfg!(debug_assertions)
+            && let (Some(details), Value::Object(values)) = (details.details, &mut values)
+        {
+            values.insert(String::from("details"), Value::String(details));
+        }
+
+        let (layout, template) = match &self.layout {
+            Some(layout) => (layout.clone(), Some(template)),
+            None => (template, None),
+        };
+
+        let page = Page::builder()
+            .registry(registry)
+            .status_code(None)
+            .layout(layout)
+            .template(template)
+            .values(Some(values))
+            .metadata(None)
+            .format(PageFormat::Html)
+            .locale(locale)
+            .redirect(None)
+            .error_message(None)
+            .build();
+
+        page.render()
+            .inspect_err(|e| tracing::error!("failed to render the error page for {status}: {e}"))
+            .ok()
+    }
+}
+
+pub(crate) async fn error_pages(
+    State(error_pages): State<Arc<ErrorPages>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let accepts_html = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    let response = next.run(request).await;
+    let status = response.status();
+    if !accepts_html || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+
+    let details = response.extensions().get::<ErrorDetails>().cloned();
+    if details.is_none() && response.headers().contains_key(header::CONTENT_TYPE) {
+        return response;
+    }
+
+    // the locale negotiated within is only known from the response
+    let locale = response
+        .extensions()
+        .get::<Locale>()
+        .map(|locale| locale.as_str().to_owned());
+    let Some(body) = error_pages.render(status, details.unwrap_or_default(), locale.clone()) else {
+        return response;
+    };
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+    if let Some(language) = locale.and_then(|locale| HeaderValue::from_str(&locale).ok()) {
+        parts.headers.insert(header::CONTENT_LANGUAGE, language);
+        parts
+            .headers
+            .append(header::VARY, HeaderValue::from_static(i18n::VARY));
+    }
+    Response::from_parts(parts, body.into())
+}
+
+/// Responds to requests which don't match a route, rendered as the 404 error page
+pub(crate) async fn not_found() -> Response {
+    StatusCode::NOT_FOUND.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_templates::{LAYOUT, TestTemplates};
+    use axum::{Router, body::Body, body::to_bytes, middleware, routing::get};
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt;
+
+    fn routes() -> Router {
+        let templates = TestTemplates::new(&[
+            LAYOUT,
+            ("errors/404.hbs", "{{status}} {{message}}"),
+            (
+                "errors/error.hbs",
+                "{{status}} {{reason}}{{#if details}} ({{details}}){{/if}}",
+            ),
+        ]);
+        let error_pages = Arc::new(ErrorPages::new(
+            templates.reloadable(),
+            Some(String::from("templates/layout/main")),
+        ));
+
+        Router::new()
+            .route(
+                "/missing",
+                get(|| async { (StatusCode::NOT_FOUND, ErrorDetails::message("gone fishing")) }),
+            )
+            .route(
+                "/broken",
+                get(|| async {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        ErrorDetails::details("database is down"),
+                    )
+                }),
+            )
+            .route(
+                "/api",
+                get(|| async {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        [(header::CONTENT_TYPE, "application/json")],
+                        "{}",
+                    )
+                }),
+            )
+            .fallback(not_found)
+            .layer(middleware::from_fn_with_state(
+                error_pages,
+                super::error_pages,
+            ))
+    }
+
+    async fn request(routes: Router, uri: &str, accept: &str) -> (StatusCode, String) {
+        let response = routes
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .header(header::ACCEPT, accept)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn render_error_pages() {
+        let routes = routes();
+        let html = "text/html,application/xhtml+xml";
+
+        assert_eq!(
+            (
+                StatusCode::NOT_FOUND,
+                String::from("<main>404 gone fishing</main>")
+            ),
+            request(routes.clone(), "/missing", html).await
+        );
+        assert_eq!(
+            (
+                StatusCode::NOT_FOUND,
+                String::from("<main>404 Not Found</main>")
+            ),
+            request(routes.clone(), "/unknown", html).await
+        );
+
+        let details = if cfg!(debug_assertions) {
+            " (database is down)"
+        } else {
+            ""
+        };
+        assert_eq!(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("<main>500 Internal Server Error{details}</main>")
+            ),
+            request(routes.clone(), "/broken", html).await
+        );
+
+        assert_eq!(
+            (StatusCode::BAD_REQUEST, String::from("{}")),
+            request(routes.clone(), "/api", html).await
+        );
+        assert_eq!(
+            (StatusCode::NOT_FOUND, String::new()),
+            request(routes, "/unknown", "application/json").await
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    http::{Request, StatusCode, header},
+};
+use tower::ServiceExt;
+use walkdir::WalkDir;
+
+use crate::assets::{AssetDirectory, AssetManifest};
+use crate::i18n::Locales;
+use crate::registry::Registry;
+use crate::sitemap::Sitemap;
+
+/// Requested for the not found page, a path no route should ever match
+const NOT_FOUND_PATH: &str = "/.loki-export/not-found";
+
+///
+/// Writes the site to a directory any static host can serve, as a mirror for when the
+/// server or database is down. Every page template of the registry and page in the
+/// sitemap is requested from the routes, under the prefix of each supported locale as
+/// well, so it's rendered by its own handler, with its layout and metadata, and written
+/// to `{path}/index.html`. The not found page is written to `404.html` along with the
+/// sitemap, robots and the asset directories, with a copy of each asset and combined
+/// bundle at its fingerprinted url. Pages which fail to render are logged and left out
+/// rather than failing the export, as are templates without a route such as fragments.
+///
+pub(crate) struct StaticExport {
+    directory: PathBuf,
+    routes: Router,
+    locales: Option<Locales>,
+}
+
+/// The outcome of requesting a page
+#[derive(Debug, PartialEq, Eq)]
+enum Exported {
+    Written,
+    /// No route matched the path
+    NotFound,
+    Failed,
+}
+
+impl StaticExport {
+    pub(crate) fn new(directory: PathBuf, routes: Router, locales: Option<Locales>) -> Self {
+        Self {
+            directory,
+            routes,
+            locales,
+        }
+    }
+
+    #[tracing::instrument(level = "info", skip_all, fields(directory = ?self.directory))]
+    pub(crate) async fn export(
+        &self,
+        registry: &Registry,
+        sitemap: &Sitemap,
+        assets: &[AssetDirectory],
+        manifest: &AssetManifest,
+    ) -> Result<()> {
+        std::fs::create_dir_all(&self.directory).with_context(|| {
+            format!("failed to create the export directory {:?}", self.directory)
+        })?;
+
+        let sitemap = sitemap
+            .entries()
+            .await
+            .into_iter()
+            .filter(|entry| !entry.is_excluded())
+            .map(|entry| entry.path().to_owned())
+            .collect::<Vec<_>>();
+        // the templates are only pages when routed, unlike the sitemap's entries
+        let templates = registry
+            .pages()
+            .into_iter()
+            .filter(|page| !sitemap.contains(page))
+            .collect::<Vec<_>>();
+
+        let mut exported = 0;
+        let mut failed = Vec::new();
+        for (path, routed) in sitemap
+            .iter()
+            .map(|path| (path, true))
+            .chain(templates.iter().map(|path| (path, false)))
+            .filter(|(path, _)| path.starts_with('/'))
+        {
+            for path in self.localized(path) {
+                let Some(file) = Self::file_name(&path) else {
+                    tracing::error!("refusing to export '{path}' outside of the export directory");
+                    failed.push(path);
+                    continue;
+                };
+                match self.export_file(&path, &file, StatusCode::OK).await? {
+                    Exported::Written => exported += 1,
+                    Exported::NotFound if !routed => {
+                        tracing::debug!("skipped template '{path}' without a route");
+                    }
+                    Exported::NotFound => {
+                        tracing::error!("failed to export '{path}', no route matched");
+                        failed.push(path);
+                    }
+                    Exported::Failed => failed.push(path),
+                }
+            }
+        }
+
+        self.export_file(NOT_FOUND_PATH, "404.html", StatusCode::NOT_FOUND)
+            .await?;
+        for file in ["sitemap.xml", "robots.txt"] {
+            let path = format!("/{file}");
+            if self.export_file(&path, file, StatusCode::OK).await? == Exported::NotFound {
+                tracing::error!("failed to export '{path}', no route matched");
+            }
+        }
+
+        // copied last to first so a file in an earlier directory of a mount replaces one
+        // in a later directory, the same file served
+        for assets in assets.iter().rev() {
+            self.copy_assets(assets)?;
+        }
+        for asset in manifest.assets() {
+            let destination = self.asset_file(asset.url())?;
+            std::fs::copy(asset.path(), &destination)
+                .with_context(|| format!("failed to copy asset {:?}", asset.path()))?;
+        }
+        for (url, combined) in manifest.combined_bundles() {
+            for url in [url, combined.url.as_str()] {
+                let destination = self.asset_file(url)?;
+                std::fs::write(&destination, &combined.contents)
+                    .with_context(|| format!("failed to write bundle {destination:?}"))?;
+            }
+        }
+
+        if failed.is_empty() {
+            tracing::info!("exported {exported} pages");
+        } else {
+            tracing::warn!(
+                failed = ?failed,
+                "exported {exported} of {} pages",
+                exported + failed.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// The path and its variant under the prefix of each supported locale
+    fn localized(&self, path: &str) -> Vec<String> {
+        let locales = self.locales.iter().flat_map(Locales::supported);
+        Some(path.to_owned())
+            .into_iter()
+            .chain(locales.map(|locale| match path {
+                "/" => format!("/{locale}"),
+                path => format!("/{locale}{path}"),
+            }))
+            .collect()
+    }
+
+    /// Requests the path writing the body to the file when the response has the
+    /// expected status
+    async fn export_file(&self, path: &str, file: &str, expected: StatusCode) -> Result<Exported> {
+        let request = Request::builder()
+            .uri(path)
+            .header(header::ACCEPT, "text/html")
+            .header(header::HOST, "localhost")
+            .body(Body::empty())
+            .with_context(|| format!("invalid export path '{path}'"))?;
+
+        let response = match self.routes.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(e) => match e {},
+        };
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND && expected != StatusCode::NOT_FOUND {
+            return Ok(Exported::NotFound);
+        }
+        if status != expected {
+            tracing::error!(%status, "failed to export '{path}'");
+            return Ok(Exported::Failed);
+        }
+
+        let body = match to_bytes(response.into_body(), usize::MAX).await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("failed to read the response of '{path}': {e}");
+                return Ok(Exported::Failed);
+            }
+        };
+
+        let target = self.directory.join(file);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory {parent:?}"))?;
+        }
+        std::fs::write(&target, body).with_context(|| format!("failed to write {target:?}"))?;
+        tracing::debug!(path, file = ?target, "exported page");
+        Ok(Exported::Written)
+    }
+
+    /// Pages are written as the index of a directory, so `/news` is served from
+    /// `news/index.html`, unless the path names a file such as `/feed.xml`. Paths with a
+    /// `..` segment have no file as they would be written outside of the directory.
+    fn file_name(path: &str) -> Option<String> {
+        let path = path
+            .split(['?', '#'])
+            .next()
+            .unwrap_or_default()
+            .trim_matches('/');
+        if path.split('/').any(|segment| segment == "..") {
+            return None;
+        }
+        let is_file = path
+            .rsplit('/')
+            .next()
+            .is_some_and(|name| name.contains('.'));
+
+        Some(match path {
+            "" => String::from("index.html"),
+            path if is_file => path.to_owned(),
+            path => format!("{path}/index.html"),
+        })
+    }
+
+    /// The file an asset url is written to, creating its directory
+    fn asset_file(&self, url: &str) -> Result<PathBuf> {
+        let file = self.directory.join(url.trim_start_matches('/'));
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory {parent:?}"))?;
+        }
+        Ok(file)
+    }
+
+    /// Copies the assets to the directory of their mount, `/assets`
+    fn copy_assets(&self, directory: &AssetDirectory) -> Result<()> {
+        let assets = directory.path();
+        let target = self
+            .directory
+            .join(directory.mount()?.trim_start_matches('/'));
+
+        for entry in WalkDir::new(assets) {
+            let entry = entry.with_context(|| format!("failed to read assets {assets:?}"))?;
+            let relative = entry.path().strip_prefix(assets)?;
+            let destination = target.join(relative);
+            if entry.file_type().is_dir() {
+                std::fs::create_dir_all(&destination)
+                    .with_context(|| format!("failed to create directory {destination:?}"))?;
+            } else {
+                std::fs::copy(entry.path(), &destination)
+                    .with_context(|| format!("failed to copy asset {:?}", entry.path()))?;
+            }
+        }
+
+        tracing::info!("copied assets {assets:?} to {target:?}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        assets::AssetBundle,
+        i18n::{Locale, localized_routes},
+        sitemap::{Robots, SitemapEntry},
+        test_templates::TestTemplates,
+    };
+    use axum::{Extension, response::IntoResponse, routing::get};
+    use futures::FutureExt;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn file_names() {
+        for (path, file) in [
+            ("/", "index.html"),
+            ("/news", "news/index.html"),
+            ("/news/", "news/index.html"),
+            ("/news/today?page=2", "news/today/index.html"),
+            ("/feed.xml", "feed.xml"),
+        ] {
+            assert_eq!(Some(String::from(file)), StaticExport::file_name(path));
+        }
+        for path in ["/../x", "/news/../../x", "/..?page=2"] {
+            assert_eq!(None, StaticExport::file_name(path));
+        }
+    }
+
+    #[tokio::test]
+    async fn export_site() {
+        let templates = TestTemplates::new(&[
+            ("content/home.hbs", "home"),
+            ("content/home.json", r#"{"sitemap": {"path": "/"}}"#),
+            ("content/about.hbs", "about"),
+            ("content/about.es.hbs", "sobre"),
+            ("content/partial.hbs", "partial"),
+            (
+                "content/admin.json",
+                r#"{"sitemap": {"path": "/admin", "exclude": true}}"#,
+            ),
+        ]);
+        let directory = templates.directory();
+        let assets = directory.join("assets");
+        std::fs::create_dir_all(assets.join("css")).unwrap();
+        std::fs::write(assets.join("css/site.css"), "body {}").unwrap();
+
+        let sitemap = Sitemap::new(
+            templates.reloadable(),
+            vec![Arc::new(|| {
+                async {
+                    vec![
+                        SitemapEntry::builder().path("/news").build(),
+                        SitemapEntry::builder().path("/broken").build(),
+                    ]
+                }
+                .boxed()
+            })],
+            Some(String::from("https://example.com")),
+            Robots::default(),
+        );
+        let locales = Locales::builder()
+            .supported(vec![String::from("es")])
+            .build();
+        let pages = Router::new()
+            .route("/", get(|| async { "home" }))
+            .route("/news", get(|| async { "news" }))
+            .route(
+                "/about",
+                get(
+                    |locale: Extension<Locale>| async move { format!("about {}", locale.as_str()) },
+                ),
+            )
+            .route("/admin", get(|| async { "admin" }))
+            .route(
+                "/broken",
+                get(|| async { StatusCode::INTERNAL_SERVER_ERROR.into_response() }),
+            );
+        let routes = localized_routes(pages, locales.clone())
+            .route("/robots.txt", get(|| async { "robots" }))
+            .route("/sitemap.xml", get(|| async { "sitemap" }))
+            .fallback(|| async { (StatusCode::NOT_FOUND, "missing") });
+
+        let output = directory.join("site");
+        let directories = [AssetDirectory::from(assets.clone())];
+        let manifest = AssetManifest::load(
+            &directories,
+            &[AssetBundle::new("css/main.css", ["css/site.css"])],
+            true,
+        )
+        .unwrap();
+        StaticExport::new(output.clone(), routes, Some(locales))
+            .export(
+                &templates.reloadable().get(),
+                &sitemap,
+                &directories,
+                &manifest,
+            )
+            .await
+            .unwrap();
+
+        let read = |file: &str| std::fs::read_to_string(output.join(file)).ok();
+        assert_eq!(Some(String::from("home")), read("index.html"));
+        assert_eq!(Some(String::from("news")), read("news/index.html"));
+        assert_eq!(Some(String::from("about en")), read("about/index.html"));
+        assert_eq!(Some(String::from("home")), read("es/index.html"));
+        assert_eq!(Some(String::from("news")), read("es/news/index.html"));
+        assert_eq!(Some(String::from("about es")), read("es/about/index.html"));
+        assert_eq!(None, read("about.es/index.html"));
+        assert_eq!(None, read("partial/index.html"));
+        assert_eq!(None, read("admin/index.html"));
+        assert_eq!(None, read("broken/index.html"));
+        assert_eq!(Some(String::from("missing")), read("404.html"));
+        assert_eq!(Some(String::from("robots")), read("robots.txt"));
+        assert_eq!(Some(String::from("body {}")), read("assets/css/site.css"));
+        assert_eq!(
+            Some(String::from("body {}")),
+            read("assets/css/site.62368a1a.css")
+        );
+        assert_eq!(Some(String::from("body{}")), read("assets/css/main.css"));
+        let combined = manifest.bundle_urls("css/main.css").unwrap().remove(0);
+        assert_eq!(
+            Some(String::from("body{}")),
+            read(combined.trim_start_matches('/'))
+        );
+    }
+}
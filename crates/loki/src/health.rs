@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json, Router,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use futures::future::BoxFuture;
+use loki_migration::MigrationStatus;
+
+/// Provides the current migration status for the health endpoint, this erases the
+/// storage type of the migration so the route does not need to be generic.
+pub type MigrationStatusFn = Arc<dyn Fn() -> BoxFuture<'static, MigrationStatus> + Send + Sync>;
+
+/// Creates the routes which report the health of the application, currently this
+/// only reports the migration status at `/healthz/migrations`.
+pub fn health_routes(migration_status: MigrationStatusFn) -> Router {
+    Router::new()
+        .route("/healthz/migrations", get(migrations))
+        .layer(Extension(migration_status))
+}
+
+/// Responds with the migration status as JSON, anything other than fully applied is
+/// reported as unavailable so deploys and uptime checks can fail on it.
+async fn migrations(Extension(migration_status): Extension<MigrationStatusFn>) -> Response {
+    let status = migration_status().await;
+    let status_code = if status.is_applied() {
+        StatusCode::OK
+    } else {
+        tracing::warn!(status = %status, "database schema does not match this build");
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status_code, Json(status)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use futures::FutureExt;
+    use pretty_assertions::assert_eq;
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    fn routes(status: MigrationStatus) -> Router {
+        health_routes(Arc::new(move || {
+            let status = status.clone();
+            async move { status }.boxed()
+        }))
+    }
+
+    async fn request(router: Router) -> (StatusCode, Value) {
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/healthz/migrations")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn migrations_applied() {
+        let (status, body) = request(routes(MigrationStatus::Applied {
+            revisions: vec![String::from("v000")],
+        }))
+        .await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!({"status": "applied", "revisions": ["v000"]}), body);
+    }
+
+    #[tokio::test]
+    async fn migrations_pending() {
+        let (status, body) = request(routes(MigrationStatus::Pending {
+            applied: vec![],
+            pending: vec![String::from("v000")],
+        }))
+        .await;
+
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(
+            json!({"status": "pending", "applied": [], "pending": ["v000"]}),
+            body
+        );
+    }
+}
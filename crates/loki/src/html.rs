@@ -0,0 +1,22 @@
+/// Escapes the text for html or xml, in element content as well as quoted attributes
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            "&lt;a href=&quot;/news?a=1&amp;b=2&quot;&gt;Fish &apos;n&apos; Chips&lt;/a&gt;",
+            escape(r#"<a href="/news?a=1&b=2">Fish 'n' Chips</a>"#)
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    extract::{OriginalUri, Request, State},
+    http::{HeaderMap, HeaderValue, header},
+    middleware::{self, Next},
+    response::Response,
+};
+use bon::Builder;
+use serde_json::Value;
+
+use crate::page_builder::PageBuilder;
+
+///
+/// The locales a site is translated to. The locale of a request comes from the url
+/// prefix of a supported locale (`/es/donate`), then the `locale` cookie and finally
+/// the `Accept-Language` header, falling back to the default which has no prefix. Pages use the `{name}.{locale}` variant of
+/// their layout, template and metadata when there is one, `content/donate.es.md`,
+/// while templates look up the messages of the catalogs in the `catalogs` directory,
+/// `es.json`, with the `t` helper and link to the pages in the locale with the
+/// `localized_url` helper.
+///
+/// ```tera
+/// <a href="{{ localized_url(path="/donate", locale=locale) }}">{{ t(key="nav.donate", locale=locale) }}</a>
+/// ```
+///
+/// ```handlebars
+/// <a href="{{localized_url "/donate"}}">{{t "nav.donate"}}</a>
+/// ```
+///
+#[derive(Builder, Debug, Clone)]
+#[builder(on(String, into))]
+pub struct Locales {
+    #[builder(default = String::from("en"))]
+    default: String,
+    /// The locales besides the default which pages can be requested in
+    #[builder(default)]
+    supported: Vec<String>,
+    /// The directory of the message catalogs, a `{locale}.json` file for each locale
+    catalogs: Option<String>,
+    #[builder(default = String::from("locale"))]
+    cookie: String,
+}
+
+/// The headers the responses of localized pages vary by
+pub(crate) const VARY: &str = "Accept-Language, Cookie";
+
+/// The locale negotiated for a request, available as a request extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale(String);
+
+impl Locale {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Locales {
+    pub fn default_locale(&self) -> &str {
+        &self.default
+    }
+
+    pub fn catalogs(&self) -> Option<&str> {
+        self.catalogs.as_deref()
+    }
+
+    /// The default and supported locales
+    pub fn locales(&self) -> impl Iterator<Item = &str> {
+        Some(self.default.as_str())
+            .into_iter()
+            .chain(self.supported.iter().map(String::as_str))
+    }
+
+    /// The supported locale matching the requested one, either exactly or by the
+    /// language alone so `es-MX` is served `es`
+    fn find(&self, requested: &str) -> Option<&str> {
+        let language = requested.split(['-', '_']).next().unwrap_or(requested);
+        self.locales()
+            .find(|locale| locale.eq_ignore_ascii_case(requested))
+            .or_else(|| {
+                self.locales()
+                    .find(|locale| locale.eq_ignore_ascii_case(language))
+            })
+    }
+
+    /// The supported locales, used as the url prefixes
+    pub fn supported(&self) -> impl Iterator<Item = &str> {
+        self.supported.iter().map(String::as_str)
+    }
+
+    /// The locale of the url prefix
+    fn path_locale(&self, path: &str) -> Option<&str> {
+        let prefix = path.trim_start_matches('/').split('/').next()?;
+        self.supported()
+            .find(|locale| locale.eq_ignore_ascii_case(prefix))
+    }
+
+    fn cookie_locale(&self, headers: &HeaderMap) -> Option<&str> {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|cookies| cookies.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == self.cookie)
+            .and_then(|(_, value)| self.find(value))
+    }
+
+    /// The supported locale with the highest quality, the earlier wins a tie
+    fn accept_language_locale(&self, headers: &HeaderMap) -> Option<&str> {
+        let accept = headers.get(header::ACCEPT_LANGUAGE)?.to_str().ok()?;
+        let mut preferred: Option<(&str, f32)> = None;
+        for language in accept.split(',') {
+            let mut parameters = language.split(';').map(str::trim);
+            let Some(locale) = parameters.next().and_then(|language| self.find(language)) else {
+                continue;
+            };
+            let quality = parameters
+                .find_map(|parameter| parameter.strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality > 0.0 && preferred.is_none_or(|(_, preferred)| quality > preferred) {
+                preferred = Some((locale, quality));
+            }
+        }
+        preferred.map(|(locale, _)| locale)
+    }
+
+    /// The locale of the request along with whether it came from the url prefix
+    pub fn negotiate(&self, path: &str, headers: &HeaderMap) -> (Locale, bool) {
+        if let Some(locale) = self.path_locale(path) {
+            return (Locale(locale.to_owned()), true);
+        }
+
+        let locale = self
+            .cookie_locale(headers)
+            .or_else(|| self.accept_language_locale(headers))
+            .unwrap_or(&self.default);
+        (Locale(locale.to_owned()), false)
+    }
+}
+
+/// Serves the page routes under the prefix of each supported locale as well,
+/// `/es/donate`, with the locale of each request negotiated for its pages
+pub(crate) fn localized_routes(pages: Router, locales: Locales) -> Router {
+    let prefixed = locales.supported().fold(Router::new(), |prefixed, locale| {
+        prefixed.nest(&format!("/{locale}"), pages.clone())
+    });
+    pages
+        .merge(prefixed)
+        .layer(middleware::from_fn_with_state(Arc::new(locales), localize))
+}
+
+/// Negotiates the locale of the request for its pages, a locale chosen by the url
+/// prefix is remembered in the cookie so the links without one stay in the locale.
+/// The pages rendered in the locale set its `Content-Language`, only those responses
+/// vary by the headers the locale is negotiated from.
+async fn localize(
+    State(locales): State<Arc<Locales>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path().to_owned(),
+        None => request.uri().path().to_owned(),
+    };
+    let (locale, path_locale) = locales.negotiate(&path, request.headers());
+    let remember = path_locale && locales.cookie_locale(request.headers()) != Some(locale.as_str());
+
+    if let Some(page) = request.extensions().get::<PageBuilder>().cloned() {
+        request
+            .extensions_mut()
+            .insert(page.locale(locale.as_str()));
+    }
+    request.extensions_mut().insert(locale.clone());
+
+    let mut response = next.run(request).await;
+    // the error pages are rendered in the locale outside of the routes
+    response.extensions_mut().insert(locale.clone());
+    let headers = response.headers_mut();
+    if headers.contains_key(header::CONTENT_LANGUAGE) {
+        headers.append(header::VARY, HeaderValue::from_static(VARY));
+    }
+    if remember
+        && let Ok(cookie) = HeaderValue::from_str(&format!(
+            "{}={}; Path=/; Max-Age=31536000; SameSite=Lax",
+            locales.cookie,
+            locale.as_str()
+        ))
+    {
+        headers.append(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+///
+/// The message catalogs of each locale, messages are looked up by their key within
+/// the catalog, `nav.donate` being the `donate` message of the `nav` object. A message
+/// missing from a locale falls back to the default locale's and then to the key.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Messages {
+    default: String,
+    catalogs: BTreeMap<String, Value>,
+}
+
+impl Messages {
+    /// Loads the `{locale}.json` catalogs of the directory
+    pub fn load<P>(directory: P, default: &str) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let directory = directory.as_ref();
+        let mut catalogs = BTreeMap::new();
+        for entry in std::fs::read_dir(directory)
+            .with_context(|| format!("failed to read the message catalogs {directory:?}"))?
+        {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(locale) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            tracing::info!(path = ?path, "registering message catalog '{locale}'");
+            let reader = std::fs::File::open(&path)
+                .with_context(|| format!("failed to open message catalog {path:?}"))?;
+            let catalog = serde_json::from_reader(reader)
+                .with_context(|| format!("failed to parse message catalog {path:?}"))?;
+            catalogs.insert(locale.to_owned(), catalog);
+        }
+
+        Ok(Self::new(default, catalogs))
+    }
+
+    pub fn new<D>(default: D, catalogs: BTreeMap<String, Value>) -> Self
+    where
+        D: Into<String>,
+    {
+        Self {
+            default: default.into(),
+            catalogs,
+        }
+    }
+
+    /// The message in the locale, its language or the default locale
+    pub fn lookup(&self, locale: Option<&str>, key: &str) -> Option<&str> {
+        let locale = locale.unwrap_or(&self.default);
+        let language = locale.split(['-', '_']).next().unwrap_or(locale);
+        [locale, language, self.default.as_str()]
+            .into_iter()
+            .filter_map(|locale| self.catalogs.get(locale))
+            .find_map(|catalog| {
+                key.split('.')
+                    .try_fold(catalog, |value, key| value.get(key))
+                    .and_then(Value::as_str)
+            })
+    }
+
+    /// The path of a page in the locale, prefixed by the locale unless it's the default
+    pub fn localized_path(&self, locale: Option<&str>, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        match locale {
+            Some(locale) if locale != self.default && path.is_empty() => format!("/{locale}"),
+            Some(locale) if locale != self.default => format!("/{locale}/{path}"),
+            _ => format!("/{path}"),
+        }
+    }
+
+    /// The message or the key when no catalog has it
+    pub fn translate(&self, locale: Option<&str>, key: &str) -> String {
+        match self.lookup(locale, key) {
+            Some(message) => message.to_owned(),
+            None => {
+                tracing::warn!(locale, "missing message '{key}'");
+                key.to_owned()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::cache::ResponseCache;
+    use crate::test_templates::{LAYOUT, TestTemplates};
+    use axum::{
+        Extension,
+        body::{Body, to_bytes},
+        routing::get,
+    };
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    fn locales() -> Locales {
+        Locales::builder()
+            .supported(vec![String::from("es")])
+            .build()
+    }
+
+    fn negotiate(path: &str, headers: &[(header::HeaderName, &'static str)]) -> (String, bool) {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect();
+        let (locale, path_locale) = locales().negotiate(path, &headers);
+        (locale.as_str().to_owned(), path_locale)
+    }
+
+    #[test]
+    fn negotiate_locale() {
+        let cases = [
+            (("en", false), negotiate("/donate", &[])),
+            (("es", true), negotiate("/es/donate", &[])),
+            (("es", true), negotiate("/es", &[])),
+            (("en", false), negotiate("/estimates", &[])),
+            // the default locale has no prefix
+            (
+                ("es", false),
+                negotiate("/en/donate", &[(header::COOKIE, "locale=es")]),
+            ),
+            (
+                ("es", false),
+                negotiate("/donate", &[(header::COOKIE, "theme=dark; locale=es")]),
+            ),
+            (
+                ("es", false),
+                negotiate(
+                    "/donate",
+                    &[(header::ACCEPT_LANGUAGE, "fr;q=1.0, es-MX;q=0.9, en;q=0.8")],
+                ),
+            ),
+            (
+                ("en", false),
+                negotiate(
+                    "/donate",
+                    &[
+                        (header::ACCEPT_LANGUAGE, "es"),
+                        (header::COOKIE, "locale=en"),
+                    ],
+                ),
+            ),
+            (
+                ("en", false),
+                negotiate("/donate", &[(header::ACCEPT_LANGUAGE, "fr, de")]),
+            ),
+        ];
+
+        for ((locale, path_locale), negotiated) in cases {
+            assert_eq!((locale.to_owned(), path_locale), negotiated);
+        }
+    }
+
+    #[tokio::test]
+    async fn localize_routes() {
+        static CACHE: ResponseCache = ResponseCache::const_ttl(8, Duration::from_secs(60));
+
+        let templates = TestTemplates::new(&[LAYOUT, ("content/page.hbs", "page in {{locale}}")]);
+        let routes = localized_routes(
+            Router::new()
+                .route(
+                    "/page",
+                    get(|page: PageBuilder| async move {
+                        page.html()
+                            .layout("templates/layout/main")
+                            .template("templates/content/page")
+                            .send()
+                    }),
+                )
+                .route(
+                    "/plain",
+                    get(|Extension(locale): Extension<Locale>| async move {
+                        format!("plain in {}", locale.as_str())
+                    }),
+                )
+                .fallback(|Extension(locale): Extension<Locale>| async move {
+                    format!("missing in {}", locale.as_str())
+                })
+                .layer(CACHE.layer()),
+            locales(),
+        )
+        .layer(Extension(PageBuilder::new(templates.reloadable())));
+
+        let request = |uri: &'static str, language: &'static str| {
+            let routes = routes.clone();
+            async move {
+                let response = routes
+                    .oneshot(
+                        Request::builder()
+                            .uri(uri)
+                            .header(header::ACCEPT_LANGUAGE, language)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let header = |name| {
+                    response
+                        .headers()
+                        .get_all(name)
+                        .iter()
+                        .map(|value| value.to_str().unwrap().to_owned())
+                        .collect::<Vec<_>>()
+                };
+                let headers = (
+                    header(header::CONTENT_LANGUAGE),
+                    header(header::VARY).contains(&String::from(VARY)),
+                    header(header::SET_COOKIE),
+                );
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (String::from_utf8(body.to_vec()).unwrap(), headers)
+            }
+        };
+
+        assert_eq!(
+            (
+                String::from("<main>page in en</main>"),
+                (vec![String::from("en")], true, vec![])
+            ),
+            request("/page", "en").await
+        );
+        assert_eq!(
+            (
+                String::from("<main>page in es</main>"),
+                (vec![String::from("es")], true, vec![])
+            ),
+            request("/page", "es-MX").await
+        );
+        let (body, (_, _, cookie)) = request("/es/page", "en").await;
+        assert_eq!("<main>page in es</main>", body);
+        assert!(cookie[0].starts_with("locale=es;"));
+
+        // only the pages rendered in the locale vary by it
+        assert_eq!(
+            (String::from("plain in es"), (vec![], false, vec![])),
+            request("/plain", "es").await
+        );
+        // the default locale has no prefix
+        let (body, _) = request("/en/page", "es").await;
+        assert_eq!("missing in es", body);
+        let (body, _) = request("/es/missing", "en").await;
+        assert_eq!("missing in es", body);
+    }
+
+    #[test]
+    fn lookup_messages() {
+        let messages = Messages::new(
+            "en",
+            BTreeMap::from([
+                (
+                    String::from("en"),
+                    json!({"nav": {"donate": "Donate", "host": "Host us"}}),
+                ),
+                (String::from("es"), json!({"nav": {"donate": "Donar"}})),
+            ]),
+        );
+
+        assert_eq!("Donar", messages.translate(Some("es"), "nav.donate"));
+        assert_eq!("Donar", messages.translate(Some("es-MX"), "nav.donate"));
+        assert_eq!("Host us", messages.translate(Some("es"), "nav.host"));
+        assert_eq!("Donate", messages.translate(None, "nav.donate"));
+        assert_eq!("nav.missing", messages.translate(Some("es"), "nav.missing"));
+        assert_eq!(None, messages.lookup(Some("es"), "nav"));
+
+        assert_eq!("/es/news", messages.localized_path(Some("es"), "/news"));
+        assert_eq!("/es", messages.localized_path(Some("es"), "/"));
+        assert_eq!("/news", messages.localized_path(Some("en"), "/news"));
+        assert_eq!("/", messages.localized_path(None, "/"));
+    }
+}
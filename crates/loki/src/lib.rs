@@ -1,15 +1,34 @@
 pub mod application;
+pub mod assets;
 pub mod cache;
+pub mod error_pages;
+mod export;
+pub mod health;
+mod html;
+pub mod i18n;
+pub mod invalidation;
+mod markdown;
 pub mod page;
 mod page_builder;
-mod page_metadata;
+pub mod page_metadata;
 mod registry;
+pub mod reload;
 pub mod server;
+pub mod sitemap;
+pub mod template_engine;
+#[cfg(test)]
+mod test_templates;
 
 pub use application::Application;
-pub use cache::Cache;
-pub use page::Page;
+pub use cache::{Backoff, Cache, CacheRegistry, KeyedCache, ResponseCache};
+pub use error_pages::ErrorDetails;
+pub use invalidation::InvalidationBus;
+pub use page::{Page, PageFormat};
 pub use page_builder::PageBuilder;
 pub use page_builder::PageError;
+pub use page_metadata::PageMetadata;
+pub use reload::Reloadable;
+pub use sitemap::{Robots, SitemapEntry};
+pub use template_engine::TemplateEngine;
 
 pub type PageResult = std::result::Result<Page, PageError>;
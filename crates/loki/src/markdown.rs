@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::page_metadata::PageMetadata;
+use crate::template_engine::helpers;
+
+///
+/// A page written in markdown, the front matter at the top of the file between `---`
+/// lines as YAML or `+++` lines as TOML holds the page's metadata along with the
+/// layout the page is rendered in. The layout is a template like any other which
+/// includes the page's html from the `content` value without escaping, while the
+/// front matter itself is available as `front_matter`.
+///
+/// ```markdown
+/// ---
+/// title: Donate
+/// description: Help us finish the movie
+/// keywords: [donate, support]
+/// layout: templates/layout/main
+/// ---
+///
+/// # Donate
+/// ```
+///
+#[derive(Debug, Clone)]
+pub(crate) struct MarkdownPage {
+    html: String,
+    layout: Option<String>,
+    metadata: PageMetadata,
+    front_matter: Value,
+}
+
+#[derive(Deserialize)]
+struct FrontMatter {
+    layout: Option<String>,
+    #[serde(flatten)]
+    metadata: PageMetadata,
+}
+
+impl MarkdownPage {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read markdown page {path:?}"))?;
+        Self::parse(&text).with_context(|| format!("failed to parse markdown page {path:?}"))
+    }
+
+    pub(crate) fn parse(text: &str) -> Result<Self> {
+        let text = text.trim_start_matches('\u{feff}');
+        let (front_matter, body) = match split_front_matter(text, "---")? {
+            Some((yaml, body)) => (
+                serde_yaml::from_str::<Option<Value>>(yaml)
+                    .with_context(|| "invalid YAML front matter")?,
+                body,
+            ),
+            None => match split_front_matter(text, "+++")? {
+                Some((toml, body)) => (
+                    Some(toml::from_str(toml).with_context(|| "invalid TOML front matter")?),
+                    body,
+                ),
+                None => (None, text),
+            },
+        };
+        let front_matter = front_matter.unwrap_or_else(|| Value::Object(Default::default()));
+        let FrontMatter { layout, metadata } =
+            serde_json::from_value(front_matter.clone()).with_context(|| "invalid front matter")?;
+
+        let html = helpers::markdown(body).map_err(anyhow::Error::msg)?;
+
+        Ok(Self {
+            html,
+            layout,
+            metadata,
+            front_matter,
+        })
+    }
+
+    pub(crate) fn html(&self) -> &str {
+        &self.html
+    }
+
+    pub(crate) fn layout(&self) -> Option<&str> {
+        self.layout.as_deref()
+    }
+
+    pub(crate) fn metadata(&self) -> &PageMetadata {
+        &self.metadata
+    }
+
+    pub(crate) fn front_matter(&self) -> &Value {
+        &self.front_matter
+    }
+}
+
+/// Splits the front matter from the body when the text starts with the fence
+fn split_front_matter<'a>(text: &'a str, fence: &str) -> Result<Option<(&'a str, &'a str)>> {
+    let mut lines = text.split_inclusive('\n');
+    if lines.next().map(str::trim_end) != Some(fence) {
+        return Ok(None);
+    }
+
+    let start = text.find('\n').map(|end| end + 1).unwrap_or(text.len());
+    let mut offset = start;
+    for line in lines {
+        if line.trim_end() == fence {
+            return Ok(Some((&text[start..offset], &text[offset + line.len()..])));
+        }
+        offset += line.len();
+    }
+
+    bail!("the front matter starting with '{fence}' is never closed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn parse_front_matter() {
+        let yaml = MarkdownPage::parse(
+            "---\ntitle: Donate\nkeywords: [donate, support]\nlayout: templates/layout/main\n---\n# Donate\n\nHelp *us*\n",
+        )
+        .unwrap();
+        assert_eq!(Some("templates/layout/main"), yaml.layout());
+        assert_eq!(
+            &json!({"title": "Donate", "keywords": ["donate", "support"], "layout": "templates/layout/main"}),
+            yaml.front_matter()
+        );
+        assert_eq!("<h1>Donate</h1>\n<p>Help <em>us</em></p>\n", yaml.html());
+
+        let toml =
+            MarkdownPage::parse("+++\r\ntitle = \"Host Us\"\r\n+++\r\nCome along\r\n").unwrap();
+        assert_eq!(None, toml.layout());
+        assert_eq!(&json!({"title": "Host Us"}), toml.front_matter());
+        assert_eq!("<p>Come along</p>\n", toml.html().replace('\r', ""));
+
+        let plain = MarkdownPage::parse("---- not front matter").unwrap();
+        assert_eq!(&json!({}), plain.front_matter());
+        assert!(MarkdownPage::parse("---\ntitle: Open\n").is_err());
+        assert!(MarkdownPage::parse("---\ntitle: [\n---\n").is_err());
+    }
+}
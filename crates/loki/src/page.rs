@@ -1,13 +1,70 @@
 use std::sync::Arc;
 
 use axum::{
-    http::{StatusCode, header},
+    Json,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::{IntoResponse, Redirect, Response},
 };
 use bon::Builder;
 use serde_json::{Map, Value};
 
-use crate::{page_metadata::PageMetadata, registry::Registry};
+use crate::{
+    PageError, error_pages::ErrorDetails, page_metadata::PageMetadata, registry::Registry,
+};
+
+///
+/// How a page responds, negotiated from the request so one handler serves the full
+/// page to browsers, the values to scripts and the content alone to htmx requests
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PageFormat {
+    /// The template rendered within its layout
+    #[default]
+    Html,
+    /// The values of the page as JSON, without the metadata
+    Json,
+    /// The template rendered without its layout
+    Fragment,
+}
+
+impl PageFormat {
+    /// The headers the format is negotiated from, for the `Vary` header
+    pub const VARY: &'static str = "Accept, HX-Request";
+
+    /// htmx requests get a fragment unless they are boosted links which swap the whole
+    /// body, otherwise JSON is sent when preferred over html by the `Accept` header
+    pub fn negotiate(headers: &HeaderMap) -> Self {
+        if headers.contains_key("hx-request") && !headers.contains_key("hx-boosted") {
+            return PageFormat::Fragment;
+        }
+
+        let Some(accept) = headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+        else {
+            return PageFormat::Html;
+        };
+
+        // the highest quality wins, the earlier type wins a tie
+        let mut preferred = (PageFormat::Html, -1.0);
+        for media_range in accept.split(',') {
+            let mut parameters = media_range.split(';').map(str::trim);
+            let format = match parameters.next() {
+                Some("application/json") => PageFormat::Json,
+                Some("text/html" | "application/xhtml+xml" | "text/*" | "*/*") => PageFormat::Html,
+                _ => continue,
+            };
+            let quality = parameters
+                .find_map(|parameter| parameter.strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality > preferred.1 {
+                preferred = (format, quality);
+            }
+        }
+        preferred.0
+    }
+}
 
 #[derive(Builder)]
 #[builder(on(_, required))]
@@ -17,7 +74,11 @@ pub struct Page {
     error_message: Option<String>,
     values: Option<Value>,
     template: Option<String>,
+    metadata: Option<PageMetadata>,
     layout: String,
+    format: PageFormat,
+    /// The negotiated locale, pages use the variants of their templates and metadata
+    locale: Option<String>,
     registry: Arc<Registry>,
 }
 
@@ -51,117 +112,163 @@ impl Page {
         }
     }*/
 
-    /// Creates the metadata for this page, this combines the default, site, layout and template in
-    /// that order returning the result. If any of those fail default or site will be returned
+    /// Creates the metadata for this page, this combines the default, site, layout, template and
+    /// request in that order returning the result. If any of those fail default or site will be returned
     fn create_metadata(&self) -> PageMetadata {
-        let mut metadata = self.registry.default_metadata();
+        let locale = self.locale.as_deref();
+        let mut metadata = self.registry.default_metadata(locale);
         let default_metadata = metadata.clone();
 
-        if let Some(layout_metadata) = self.registry.find_metadata(&self.layout) {
-            if let Err(e) = metadata.merge(&layout_metadata) {
-                tracing::error!(
-                    layout = ?layout_metadata,
-                    metadata = ?metadata,
-                    default = ?default_metadata,
-                    error = ?e,
-                    "failed to merge layout metadata {} into metadata returning default: {e}",
-                    self.layout
-                );
-                return default_metadata;
-            }
+        if let Some(layout_metadata) = self.registry.find_localized_metadata(&self.layout, locale)
+            && let Err(e) = metadata.merge(&layout_metadata)
+        {
+            tracing::error!(
+                layout = ?layout_metadata,
+                metadata = ?metadata,
+                default = ?default_metadata,
+                error = ?e,
+                "failed to merge layout metadata {} into metadata returning default: {e}",
+                self.layout
+            );
+            return default_metadata;
         }
 
-        if let Some(template) = &self.template {
-            if let Some(template_metadata) = self.registry.find_metadata(template) {
-                if let Err(e) = metadata.merge(&template_metadata) {
-                    tracing::error!(
-                        metadata = ?metadata,
-                        default = ?default_metadata,
-                        template = ?template_metadata,
-                        error = ?e,
-                        "failed to merge template metadata into metadata: {e}"
-                    );
-                    return default_metadata;
-                }
-            }
+        if let Some(template) = &self.template
+            && let Some(template_metadata) = self.registry.find_localized_metadata(template, locale)
+            && let Err(e) = metadata.merge(&template_metadata)
+        {
+            tracing::error!(
+                metadata = ?metadata,
+                default = ?default_metadata,
+                template = ?template_metadata,
+                error = ?e,
+                "failed to merge template metadata into metadata: {e}"
+            );
+            return default_metadata;
+        }
+
+        if let Some(request_metadata) = &self.metadata
+            && let Err(e) = metadata.merge(request_metadata)
+        {
+            tracing::error!(
+                metadata = ?metadata,
+                default = ?default_metadata,
+                request = ?request_metadata,
+                error = ?e,
+                "failed to merge request metadata into metadata: {e}"
+            );
+            return default_metadata;
         }
 
         metadata
     }
 }
 
-impl IntoResponse for Page {
-    fn into_response(self) -> Response {
-        // if we have a location issue a redirect
-        if let Some(uri) = self.redirect {
-            return Redirect::temporary(&uri).into_response();
-        }
-
-        // if we have a status code apply it, if its an error we have
-        let status_code = self.status_code.unwrap_or(StatusCode::OK);
-        if !status_code.is_success() {
-            return Response::builder()
-                .status(status_code)
-                .header(header::CONTENT_TYPE, "text/html")
-                .body(
-                    self.error_message
-                        .unwrap_or_else(|| status_code.to_string())
-                        .into(),
-                )
-                .unwrap_or_else(|e| {
-                    tracing::error!(
-                        "failed to create error response {}: {e:?}",
-                        status_code.as_u16()
-                    );
-                    status_code.into_response()
-                });
-        }
-
+impl Page {
+    /// Renders the page with its metadata applied to the values, fragments only render
+    /// the template
+    pub(crate) fn render(&self) -> Result<String, PageError> {
         let metadata = self.create_metadata();
-        let mut values: Map<String, Value> = match self.values {
+        let mut values: Map<String, Value> = match &self.values {
             None | Some(Value::Null) => Default::default(),
             Some(Value::Object(map)) => map.clone(),
-            _ => {
-                tracing::error!("expected object for values got :: {:?}", self.values);
-                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            Some(values) => {
+                return Err(PageError::Serialization {
+                    name: String::from("values"),
+                    message: format!("expected object for values got :: {values:?}"),
+                });
             }
         };
 
-        metadata.apply(&mut values).unwrap();
-        values.insert(String::from("layout"), Value::String(self.layout.clone()));
-        if let Some(template) = &self.template {
+        metadata
+            .apply(&mut values)
+            .map_err(|e| PageError::Metadata(format!("{e:#}")))?;
+        // the variants of the layout and template in the locale when there are some
+        let layout = self
+            .registry
+            .localized(&self.layout, self.locale.as_deref());
+        let template = self
+            .template
+            .as_deref()
+            .map(|template| self.registry.localized(template, self.locale.as_deref()));
+        values.insert(String::from("layout"), Value::String(layout.clone()));
+        if let Some(template) = &template {
             values.insert(
                 String::from("content_template"),
                 Value::String(template.clone()),
             );
         }
+        if let Some(locale) = &self.locale {
+            values.insert(String::from("locale"), Value::String(locale.clone()));
+        }
 
-        let body = self
-            .registry
-            .handlebars()
-            .render(&self.layout, &values)
-            .unwrap();
+        let body = match (self.format, &template) {
+            (PageFormat::Fragment, Some(template)) => {
+                self.registry.render_fragment(template, &values)?
+            }
+            _ => self
+                .registry
+                .render(&layout, template.as_deref(), &values)?,
+        };
 
         tracing::debug!(
             layout = self.layout,
             template = ?self.template,
+            format = ?self.format,
             values = ?values,
             "successfully rendered page"
         );
+        Ok(body)
+    }
+}
+
+impl IntoResponse for Page {
+    fn into_response(self) -> Response {
+        // if we have a location issue a redirect
+        if let Some(uri) = self.redirect {
+            return Redirect::temporary(&uri).into_response();
+        }
+
+        // if we have a status code apply it, errors are rendered by the error pages
+        let status_code = self.status_code.unwrap_or(StatusCode::OK);
+        if !status_code.is_success() {
+            let details = match self.error_message {
+                Some(message) => ErrorDetails::message(message),
+                None => ErrorDetails::default(),
+            };
+            return (status_code, details).into_response();
+        }
+
+        let vary = (header::VARY, HeaderValue::from_static(PageFormat::VARY));
+        if self.format == PageFormat::Json {
+            let values = self
+                .values
+                .unwrap_or_else(|| Value::Object(Default::default()));
+            return ([vary], Json(values)).into_response();
+        }
+
+        let body = match self.render() {
+            Ok(body) => body,
+            Err(e) => return e.into_response(),
+        };
 
-        Response::builder()
+        let mut response = Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, "text/html")
-            .body(body.into())
-            .unwrap_or_else(|e| {
-                tracing::error!(
-                    template = ?self.template,
-                    layout = self.layout,
-                    error = ?e,
-                    "failed to render template"
-                );
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            })
+            .header(vary.0, vary.1);
+        // the pages rendered in a negotiated locale, see `i18n::localize`
+        if let Some(locale) = &self.locale {
+            response = response.header(header::CONTENT_LANGUAGE, locale);
+        }
+        response.body(body.into()).unwrap_or_else(|e| {
+            tracing::error!(
+                template = ?self.template,
+                layout = self.layout,
+                error = ?e,
+                "failed to render template"
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })
     }
 }
 
@@ -222,3 +329,109 @@ mod tests {
         );
     }
 }*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_templates::{LAYOUT, TestTemplates};
+    use axum::body::to_bytes;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    header::HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn negotiate() {
+        let cases = [
+            (PageFormat::Html, headers(&[])),
+            (
+                PageFormat::Html,
+                headers(&[("accept", "text/html,application/xhtml+xml,*/*;q=0.8")]),
+            ),
+            (PageFormat::Json, headers(&[("accept", "application/json")])),
+            (
+                PageFormat::Json,
+                headers(&[("accept", "text/html;q=0.5, application/json")]),
+            ),
+            (
+                PageFormat::Json,
+                headers(&[("accept", "application/json, text/html")]),
+            ),
+            (
+                PageFormat::Html,
+                headers(&[("accept", "text/html, application/json")]),
+            ),
+            (PageFormat::Fragment, headers(&[("hx-request", "true")])),
+            (
+                PageFormat::Html,
+                headers(&[("hx-request", "true"), ("hx-boosted", "true")]),
+            ),
+        ];
+
+        for (format, headers) in cases {
+            assert_eq!(format, PageFormat::negotiate(&headers), "{headers:?}");
+        }
+    }
+
+    fn page(format: PageFormat) -> Page {
+        let templates = TestTemplates::new(&[LAYOUT, ("content/news.hbs", "{{news}}")]);
+        let registry = templates.registry(vec![]);
+
+        Page::builder()
+            .registry(Arc::new(registry))
+            .status_code(None)
+            .layout(String::from("templates/layout/main"))
+            .template(Some(String::from("templates/content/news")))
+            .values(Some(json!({"news": "today"})))
+            .metadata(None)
+            .format(format)
+            .locale(None)
+            .redirect(None)
+            .error_message(None)
+            .build()
+    }
+
+    async fn respond(format: PageFormat) -> (String, String) {
+        let response = page(format).into_response();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(PageFormat::VARY, response.headers()[header::VARY]);
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn respond_in_format() {
+        assert_eq!(
+            (
+                String::from("text/html"),
+                String::from("<main>today</main>")
+            ),
+            respond(PageFormat::Html).await
+        );
+        assert_eq!(
+            (
+                String::from("application/json"),
+                String::from(r#"{"news":"today"}"#)
+            ),
+            respond(PageFormat::Json).await
+        );
+        assert_eq!(
+            (String::from("text/html"), String::from("today")),
+            respond(PageFormat::Fragment).await
+        );
+    }
+}
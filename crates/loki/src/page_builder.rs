@@ -1,29 +1,60 @@
 use std::fmt::Debug;
 use std::sync::Arc;
 
+use crate::error_pages::ErrorDetails;
+use crate::page::PageFormat;
+use crate::page_metadata::PageMetadata;
 use crate::registry::Registry;
+use crate::reload::Reloadable;
 use crate::{Page, PageResult};
 use axum::body::Body;
-use axum::http::{Response, StatusCode, header};
+use axum::extract::FromRequestParts;
+use axum::http::{Response, StatusCode, header, request::Parts};
 use axum::response::IntoResponse;
 use serde::Serialize;
 use serde_json::{Map, Value};
 use thiserror::Error;
 
+///
+/// Builds the pages of a request, extracted by handlers as `page: PageBuilder` the
+/// page responds in the format negotiated from the request's headers, see
+/// [PageFormat::negotiate]. Taken as an `Extension<PageBuilder>` pages are always html.
+///
 #[derive(Clone)]
 pub struct PageBuilder {
-    registry: Arc<Registry>,
+    registry: Reloadable<Registry>,
+    format: PageFormat,
+    locale: Option<String>,
 }
 
 impl PageBuilder {
-    pub(crate) fn new(registry: Registry) -> Self {
+    pub(crate) fn new(registry: Reloadable<Registry>) -> Self {
         Self {
-            registry: Arc::new(registry),
+            registry,
+            format: PageFormat::Html,
+            locale: None,
         }
     }
 
+    /// The locale negotiated for the request, see [Locales](crate::i18n::Locales)
+    pub(crate) fn locale<L>(mut self, locale: L) -> Self
+    where
+        L: Into<String>,
+    {
+        self.locale = Some(locale.into());
+        self
+    }
+
+    /// Overrides the negotiated format, such as a `.json` route responding with JSON
+    pub fn format(mut self, format: PageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
     pub fn html(self) -> HtmlPageBuilder {
-        HtmlPageBuilder::new(self.registry.clone())
+        let mut page = HtmlPageBuilder::new(self.registry.get()).format(self.format);
+        page.locale = self.locale;
+        page
     }
 
     pub fn raw_html<H>(&self, html: H) -> Response<Body>
@@ -45,6 +76,26 @@ impl PageBuilder {
     }
 }
 
+impl<S> FromRequestParts<S> for PageBuilder
+where
+    S: Send + Sync,
+{
+    type Rejection = axum::response::Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(page) = parts.extensions.get::<PageBuilder>() else {
+            tracing::error!("the page builder is only available to routes of the application");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorDetails::details("missing the page builder extension"),
+            )
+                .into_response());
+        };
+
+        Ok(page.clone().format(PageFormat::negotiate(&parts.headers)))
+    }
+}
+
 /*impl<S> PageBuilderHtmlBuilder<S>
 where
     S: page_builder_html_builder::State,
@@ -75,6 +126,11 @@ pub struct HtmlPageBuilder {
     status: Option<StatusCode>,
     layout: Option<String>,
     template: Option<String>,
+    metadata: Option<PageMetadata>,
+    format: PageFormat,
+    locale: Option<String>,
+    /// The first value which failed to serialize, reported when the page is sent
+    error: Option<PageError>,
 }
 
 impl Debug for HtmlPageBuilder {
@@ -83,6 +139,9 @@ impl Debug for HtmlPageBuilder {
             .field("layout", &self.layout)
             .field("template", &self.template)
             .field("status", &self.status)
+            .field("metadata", &self.metadata)
+            .field("format", &self.format)
+            .field("locale", &self.locale)
             .field("values", &self.page_values)
             .finish()
     }
@@ -94,35 +153,75 @@ pub enum PageError {
     NoLayout,
     #[error("The provided layout ({0}) as invalid")]
     LayoutNotFound(String),
-    #[error("The template {1} was not found for a page using layout {1}")]
+    #[error("The template {1} was not found for a page using layout {0}")]
     TemplateNotFound(String, String),
+    #[error("Failed to render template {template}{}: {message}", line.map(|line| format!(" at line {line}")).unwrap_or_default())]
+    Render {
+        template: String,
+        line: Option<usize>,
+        message: String,
+    },
+    #[error("The partial {partial} used by template {template} was not found")]
+    MissingPartial { template: String, partial: String },
+    #[error("Failed to serialize the page value {name}: {message}")]
+    Serialization { name: String, message: String },
+    #[error("Failed to apply the page metadata: {0}")]
+    Metadata(String),
 }
 
 impl IntoResponse for PageError {
     fn into_response(self) -> axum::response::Response {
-        tracing::error!(
-            error = self.to_string(),
-            "generating an internal server error page"
-        );
-        Response::builder()
+        match &self {
+            PageError::Render {
+                template,
+                line,
+                message,
+            } => tracing::error!(template, line, message, "failed to render page: {self}"),
+            PageError::MissingPartial { template, partial } => {
+                tracing::error!(template, partial, "failed to render page: {self}")
+            }
+            PageError::Serialization { name, message } => {
+                tracing::error!(value = name, message, "failed to render page: {self}")
+            }
+            _ => tracing::error!(
+                error = self.to_string(),
+                "generating an internal server error page"
+            ),
+        }
+
+        let mut response = Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .header(header::CONTENT_TYPE, "text/plain")
             .body(self.to_string().into())
-            .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        response
+            .extensions_mut()
+            .insert(ErrorDetails::details(self.to_string()));
+        response
     }
 }
 
 impl HtmlPageBuilder {
     fn new(registry: Arc<Registry>) -> Self {
         Self {
-            registry: registry,
+            registry,
             page_values: Map::new(),
             status: None,
             layout: None,
             template: None,
+            metadata: None,
+            format: PageFormat::Html,
+            locale: None,
+            error: None,
         }
     }
 
+    /// The format the page responds with, negotiated by the [PageBuilder] extractor
+    pub fn format(mut self, format: PageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
     pub fn status(mut self, status: StatusCode) -> Self {
         self.status.replace(status);
         self
@@ -144,13 +243,32 @@ impl HtmlPageBuilder {
         self
     }
 
+    /// Metadata for this request, merged after the layout and template metadata such
+    /// as the title or Open Graph image of a news article
+    pub fn metadata(mut self, metadata: PageMetadata) -> Self {
+        self.metadata.replace(metadata);
+        self
+    }
+
+    /// Adds a value to the page, a value which fails to serialize is reported as an
+    /// error when the page is sent
     pub fn value<S, V>(mut self, name: S, value: V) -> Self
     where
         V: Serialize,
         S: Into<String>,
     {
-        let value = serde_json::to_value(&value).expect("failed to serialize value");
-        self.page_values.insert(name.into(), value);
+        let name = name.into();
+        match serde_json::to_value(&value) {
+            Ok(value) => {
+                self.page_values.insert(name, value);
+            }
+            Err(e) => {
+                self.error.get_or_insert(PageError::Serialization {
+                    name,
+                    message: e.to_string(),
+                });
+            }
+        }
         self
     }
 
@@ -169,30 +287,42 @@ impl HtmlPageBuilder {
     }
 
     pub fn send(self) -> PageResult {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
         let values = if self.page_values.is_empty() {
             None
         } else {
             Some(Value::Object(self.page_values))
         };
 
-        let Some(layout) = &self.layout else {
+        // markdown pages are rendered in the layout declared by their front matter
+        let layout = self
+            .template
+            .as_deref()
+            .map(|template| self.registry.localized(template, self.locale.as_deref()))
+            .and_then(|template| self.registry.markdown_layout(template))
+            .map(ToOwned::to_owned)
+            .or(self.layout);
+        let Some(layout) = &layout else {
             tracing::error!("pages must have a valid layout");
             return Err(PageError::NoLayout);
         };
 
-        if !self.registry.has_metadata(layout) {
+        if !self.registry.has_template(layout) {
             tracing::error!("layout '{layout}' was not found");
             return Err(PageError::LayoutNotFound(layout.clone()));
         }
 
-        if let Some(template) = &self.template {
-            if !self.registry.has_template(template) {
-                tracing::error!("page references an invalid template '{template}");
-                return Err(PageError::TemplateNotFound(
-                    layout.clone(),
-                    template.clone(),
-                ));
-            }
+        if let Some(template) = &self.template
+            && !self.registry.has_template(template)
+        {
+            tracing::error!("page references an invalid template '{template}");
+            return Err(PageError::TemplateNotFound(
+                layout.clone(),
+                template.clone(),
+            ));
         }
 
         Ok(Page::builder()
@@ -201,8 +331,34 @@ impl HtmlPageBuilder {
             .layout(layout.clone())
             .template(self.template)
             .values(values)
+            .metadata(self.metadata)
+            .format(self.format)
+            .locale(self.locale)
             .redirect(None)
             .error_message(None)
             .build())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn value_serialization_error() {
+        let values = HashMap::from([((1, 2), 3)]);
+        let result = HtmlPageBuilder::new(Arc::new(Registry::new()))
+            .layout("layout")
+            .value("coordinates", values)
+            .value("name", "loki")
+            .send();
+
+        match result {
+            Err(PageError::Serialization { name, .. }) => assert_eq!("coordinates", name),
+            result => panic!("expected a serialization error got {:?}", result.err()),
+        }
+    }
+}
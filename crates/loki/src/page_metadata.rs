@@ -1,17 +1,121 @@
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use bon::Builder;
+use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
-#[derive(Deserialize, Debug, Clone, Default)]
+use crate::html::escape;
+use crate::sitemap::SitemapEntry;
+
+///
+/// The metadata of a page, loaded from the `.json` file next to a template and merged
+/// from the site default through the layout and template to the request. Besides the
+/// `title`, `description` and `keywords` the values include `canonical`, `robots`,
+/// `open_graph`, `twitter` and `structured_data` along with `meta_tags`, the html for
+/// the social, canonical, robots and JSON-LD tags which layouts include in the head
+/// without escaping. The `sitemap` section lists the page in `/sitemap.xml`.
+///
+#[derive(Builder, Deserialize, Debug, Clone, Default)]
+#[builder(on(String, into))]
+#[serde(default)]
 pub struct PageMetadata {
+    /// JSON-LD documents such as a `Movie`, `Event` or `NewsArticle`
+    #[builder(field)]
+    structured_data: Vec<Value>,
     title: Option<String>,
     description: Option<String>,
+    #[builder(default)]
     keywords: Vec<String>,
+    /// The preferred url of the page for search engines
+    canonical: Option<String>,
+    /// Directives for crawlers such as `noindex` or `nofollow`
+    #[builder(default)]
+    robots: Vec<String>,
+    open_graph: Option<OpenGraph>,
+    twitter: Option<TwitterCard>,
+    sitemap: Option<SitemapEntry>,
+}
+
+/// The Open Graph properties used when a page is shared, the title, description and
+/// url fall back to the page's own
+#[derive(Builder, Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[builder(on(String, into))]
+#[serde(default)]
+pub struct OpenGraph {
+    title: Option<String>,
+    description: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    url: Option<String>,
+    image: Option<String>,
+    image_alt: Option<String>,
+    video: Option<String>,
+    site_name: Option<String>,
+    locale: Option<String>,
+}
+
+/// The twitter card properties, anything missing falls back to the Open Graph ones
+#[derive(Builder, Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[builder(on(String, into))]
+#[serde(default)]
+pub struct TwitterCard {
+    card: Option<String>,
+    site: Option<String>,
+    creator: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    image: Option<String>,
+}
+
+impl<S> PageMetadataBuilder<S>
+where
+    S: page_metadata_builder::State,
+{
+    /// Adds a JSON-LD document to the page
+    pub fn structured_data(mut self, data: Value) -> Self {
+        self.structured_data.push(data);
+        self
+    }
+}
+
+impl OpenGraph {
+    fn merge(&mut self, other: &Self) {
+        let other = other.clone();
+        self.title = other.title.or(self.title.take());
+        self.description = other.description.or(self.description.take());
+        self.kind = other.kind.or(self.kind.take());
+        self.url = other.url.or(self.url.take());
+        self.image = other.image.or(self.image.take());
+        self.image_alt = other.image_alt.or(self.image_alt.take());
+        self.video = other.video.or(self.video.take());
+        self.site_name = other.site_name.or(self.site_name.take());
+        self.locale = other.locale.or(self.locale.take());
+    }
+}
+
+impl TwitterCard {
+    fn merge(&mut self, other: &Self) {
+        let other = other.clone();
+        self.card = other.card.or(self.card.take());
+        self.site = other.site.or(self.site.take());
+        self.creator = other.creator.or(self.creator.take());
+        self.title = other.title.or(self.title.take());
+        self.description = other.description.or(self.description.take());
+        self.image = other.image.or(self.image.take());
+    }
 }
 
 impl PageMetadata {
+    pub fn sitemap(&self) -> Option<&SitemapEntry> {
+        self.sitemap.as_ref()
+    }
+
+    /// Pages asking not to be indexed are left out of the sitemap
+    pub fn is_noindex(&self) -> bool {
+        self.robots.iter().any(|robots| robots == "noindex")
+    }
+
     pub fn apply(&self, value: &mut Map<String, Value>) -> Result<()> {
         if let Some(title) = self.title.clone() {
             value.insert(String::from("title"), Value::String(title));
@@ -28,6 +132,39 @@ impl PageMetadata {
             value.insert(String::from("keywords"), Value::String(keywords));
         }
 
+        value.insert(
+            String::from("canonical"),
+            self.canonical
+                .clone()
+                .map(Value::String)
+                .unwrap_or_default(),
+        );
+
+        if self.robots.is_empty() {
+            value.insert(String::from("robots"), Value::Null);
+        } else {
+            value.insert(String::from("robots"), Value::String(self.robots.join(",")));
+        }
+
+        let open_graph = self.open_graph();
+        value.insert(
+            String::from("open_graph"),
+            serde_json::to_value(&open_graph).with_context(|| "failed to serialize open graph")?,
+        );
+        value.insert(
+            String::from("twitter"),
+            match self.twitter_card(&open_graph) {
+                Some(twitter) => serde_json::to_value(&twitter)
+                    .with_context(|| "failed to serialize twitter card")?,
+                None => Value::Null,
+            },
+        );
+        value.insert(
+            String::from("structured_data"),
+            Value::Array(self.structured_data.clone()),
+        );
+        value.insert(String::from("meta_tags"), Value::String(self.meta_tags()?));
+
         Ok(())
     }
 
@@ -44,7 +181,7 @@ impl PageMetadata {
             (Some(descr), Some(other_descr)) => {
                 let mut other_descr = other_descr.clone();
                 other_descr.push(' ');
-                other_descr.push_str(&descr);
+                other_descr.push_str(descr);
                 Some(other_descr)
             }
             (None, Some(descr)) => Some(descr.to_owned()),
@@ -72,8 +209,156 @@ impl PageMetadata {
             self.keywords = keywords;
         }
 
+        if other.canonical.is_some() {
+            self.canonical = other.canonical.clone();
+        }
+
+        if !other.robots.is_empty() {
+            self.robots = other.robots.clone();
+        }
+
+        match (&mut self.open_graph, &other.open_graph) {
+            (Some(open_graph), Some(other)) => open_graph.merge(other),
+            (None, Some(other)) => self.open_graph = Some(other.clone()),
+            _ => {}
+        }
+
+        match (&mut self.twitter, &other.twitter) {
+            (Some(twitter), Some(other)) => twitter.merge(other),
+            (None, Some(other)) => self.twitter = Some(other.clone()),
+            _ => {}
+        }
+
+        if other.sitemap.is_some() {
+            self.sitemap = other.sitemap.clone();
+        }
+
+        self.structured_data
+            .extend(other.structured_data.iter().cloned());
+
         Ok(())
     }
+
+    /// Replaces the values with those set by the other metadata of the same page, such
+    /// as the front matter of a markdown page over its `.json` file, unlike [merge]
+    /// which combines a page's metadata with its layout's
+    ///
+    /// [merge]: PageMetadata::merge
+    pub(crate) fn overlay(&mut self, other: &Self) {
+        if other.title.is_some() {
+            self.title = other.title.clone();
+        }
+        if other.description.is_some() {
+            self.description = other.description.clone();
+        }
+        if !other.keywords.is_empty() {
+            self.keywords = other.keywords.clone();
+        }
+        if other.canonical.is_some() {
+            self.canonical = other.canonical.clone();
+        }
+        if !other.robots.is_empty() {
+            self.robots = other.robots.clone();
+        }
+        if other.open_graph.is_some() {
+            self.open_graph = other.open_graph.clone();
+        }
+        if other.twitter.is_some() {
+            self.twitter = other.twitter.clone();
+        }
+        if other.sitemap.is_some() {
+            self.sitemap = other.sitemap.clone();
+        }
+        self.structured_data
+            .extend(other.structured_data.iter().cloned());
+    }
+
+    /// The Open Graph properties with the page's title, description and canonical
+    /// url filling in the missing ones
+    fn open_graph(&self) -> OpenGraph {
+        let mut open_graph = self.open_graph.clone().unwrap_or_default();
+        open_graph.title = open_graph.title.or(self.title.clone());
+        open_graph.description = open_graph.description.or(self.description.clone());
+        open_graph.url = open_graph.url.or(self.canonical.clone());
+        open_graph
+    }
+
+    fn twitter_card(&self, open_graph: &OpenGraph) -> Option<TwitterCard> {
+        let mut twitter = self.twitter.clone()?;
+        twitter.title = twitter.title.or(open_graph.title.clone());
+        twitter.description = twitter.description.or(open_graph.description.clone());
+        twitter.image = twitter.image.or(open_graph.image.clone());
+        twitter.card = twitter.card.or_else(|| match twitter.image {
+            Some(_) => Some(String::from("summary_large_image")),
+            None => Some(String::from("summary")),
+        });
+        Some(twitter)
+    }
+
+    fn meta_tags(&self) -> Result<String> {
+        let mut tags = Vec::new();
+        if let Some(canonical) = &self.canonical {
+            tags.push(format!(
+                r#"<link rel="canonical" href="{}" />"#,
+                escape(canonical)
+            ));
+        }
+
+        if !self.robots.is_empty() {
+            tags.push(meta("name", "robots", &self.robots.join(",")));
+        }
+
+        let open_graph = self.open_graph();
+        for (property, content) in [
+            ("og:title", &open_graph.title),
+            ("og:description", &open_graph.description),
+            ("og:type", &open_graph.kind),
+            ("og:url", &open_graph.url),
+            ("og:image", &open_graph.image),
+            ("og:image:alt", &open_graph.image_alt),
+            ("og:video", &open_graph.video),
+            ("og:site_name", &open_graph.site_name),
+            ("og:locale", &open_graph.locale),
+        ] {
+            if let Some(content) = content {
+                tags.push(meta("property", property, content));
+            }
+        }
+
+        if let Some(twitter) = self.twitter_card(&open_graph) {
+            for (name, content) in [
+                ("twitter:card", &twitter.card),
+                ("twitter:site", &twitter.site),
+                ("twitter:creator", &twitter.creator),
+                ("twitter:title", &twitter.title),
+                ("twitter:description", &twitter.description),
+                ("twitter:image", &twitter.image),
+            ] {
+                if let Some(content) = content {
+                    tags.push(meta("name", name, content));
+                }
+            }
+        }
+
+        for data in &self.structured_data {
+            // a closing script tag within a string would end the script early
+            let json = serde_json::to_string(data)
+                .with_context(|| "failed to serialize structured data")?
+                .replace("</", r"<\/");
+            tags.push(format!(
+                r#"<script type="application/ld+json">{json}</script>"#
+            ));
+        }
+
+        Ok(tags.join("\n"))
+    }
+}
+
+fn meta(attribute: &str, name: &str, content: &str) -> String {
+    format!(
+        r#"<meta {attribute}="{name}" content="{}" />"#,
+        escape(content)
+    )
 }
 
 impl TryFrom<&Path> for PageMetadata {
@@ -83,7 +368,7 @@ impl TryFrom<&Path> for PageMetadata {
         let mut path = PathBuf::from(value);
         path.set_extension("json");
 
-        return if path.exists() {
+        if path.exists() {
             tracing::debug!("laoding page metadata {path:?}");
             let reader = std::fs::File::open(&path)
                 .with_context(|| format!("failed to open file {path:?}"))?;
@@ -91,7 +376,7 @@ impl TryFrom<&Path> for PageMetadata {
                 .with_context(|| format!("failed to parse metadata {path:?}"))?)
         } else {
             Ok(Self::default())
-        };
+        }
     }
 }
 
@@ -105,9 +390,83 @@ impl TryFrom<&PathBuf> for PageMetadata {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
     #[test]
     fn simple() {}
 
     #[test]
     fn other() {}
+
+    #[test]
+    fn merge_social() {
+        let mut metadata: PageMetadata = serde_json::from_value(json!({
+            "title": "Site",
+            "open_graph": {"type": "video.movie", "site_name": "Site", "image": "/poster.jpg"},
+            "twitter": {"site": "@site"},
+            "robots": ["index"],
+            "structured_data": [{"@type": "Movie"}]
+        }))
+        .unwrap();
+
+        let template: PageMetadata = serde_json::from_value(json!({
+            "title": "News",
+            "open_graph": {"type": "website"},
+            "robots": ["noindex", "nofollow"],
+        }))
+        .unwrap();
+        metadata.merge(&template).unwrap();
+
+        let request = PageMetadata::builder()
+            .canonical("https://example.com/news")
+            .structured_data(json!({"@type": "NewsArticle"}))
+            .build();
+        metadata.merge(&request).unwrap();
+
+        let mut values = Map::new();
+        metadata.apply(&mut values).unwrap();
+        assert_eq!(json!("https://example.com/news"), values["canonical"]);
+        assert_eq!(json!("noindex,nofollow"), values["robots"]);
+        assert_eq!(
+            json!({
+                "title": "News - Site",
+                "description": null,
+                "type": "website",
+                "url": "https://example.com/news",
+                "image": "/poster.jpg",
+                "image_alt": null,
+                "video": null,
+                "site_name": "Site",
+                "locale": null,
+            }),
+            values["open_graph"]
+        );
+        assert_eq!(json!("summary_large_image"), values["twitter"]["card"]);
+        assert_eq!(json!("/poster.jpg"), values["twitter"]["image"]);
+        assert_eq!(
+            json!([{"@type": "Movie"}, {"@type": "NewsArticle"}]),
+            values["structured_data"]
+        );
+    }
+
+    #[test]
+    fn meta_tags() {
+        let metadata = PageMetadata::builder()
+            .title("Fish & \"Chips\"")
+            .open_graph(OpenGraph::builder().kind("article").build())
+            .structured_data(json!({"name": "</script><script>"}))
+            .build();
+
+        assert_eq!(
+            [
+                r#"<meta property="og:title" content="Fish &amp; &quot;Chips&quot;" />"#,
+                r#"<meta property="og:type" content="article" />"#,
+                r#"<script type="application/ld+json">{"name":"<\/script><script>"}</script>"#,
+            ]
+            .join("\n"),
+            metadata.meta_tags().unwrap()
+        );
+    }
 }
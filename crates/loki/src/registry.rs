@@ -1,35 +1,86 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     ffi::OsStr,
     fs::{DirEntry, read_dir},
     path::PathBuf,
+    sync::Arc,
 };
 
 use anyhow::{Context, Result, bail};
-use handlebars::Handlebars;
+use axum::http::StatusCode;
+use serde_json::{Map, Value};
 use tracing::instrument;
 
+use crate::PageError;
+use crate::assets::AssetManifest;
+use crate::i18n::Messages;
+use crate::markdown::MarkdownPage;
 use crate::page_metadata::PageMetadata;
+use crate::sitemap::SitemapEntry;
+use crate::template_engine::{HandlebarsEngine, TemplateEngine, TemplateFile};
 
 pub struct Registry {
     page_metatdata: BTreeMap<String, PageMetadata>,
-    handlebars: Handlebars<'static>,
+    engines: Vec<Box<dyn TemplateEngine>>,
+    templates: BTreeSet<String>,
+    /// The `.md` pages rendered within their layout
+    markdown: BTreeMap<String, MarkdownPage>,
     site_metadata: Option<String>,
 }
 
 impl Registry {
+    /// Creates a registry rendering handlebars templates
     pub fn new() -> Self {
+        Self::with_engines(Vec::new())
+    }
+
+    /// Creates a registry rendering templates with the engines, handlebars is
+    /// included unless one of the engines handles `.hbs` templates
+    pub fn with_engines(mut engines: Vec<Box<dyn TemplateEngine>>) -> Self {
+        if !engines.iter().any(|engine| engine.extension() == "hbs") {
+            engines.push(Box::new(HandlebarsEngine::new()));
+        }
+
         Self {
             page_metatdata: BTreeMap::new(),
-            handlebars: Handlebars::new(),
+            engines,
+            templates: BTreeSet::new(),
+            markdown: BTreeMap::new(),
             site_metadata: None,
         }
     }
 
+    /// Creates a registry from the templates and metadata within the directories
+    pub fn load<S>(directories: &[S], engines: Vec<Box<dyn TemplateEngine>>) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let mut registry = Self::with_engines(engines);
+        for directory in directories {
+            registry.register_directory(directory)?;
+        }
+        Ok(registry)
+    }
+
     pub fn set_site_metadata(&mut self, metadata: Option<String>) {
         self.site_metadata = metadata
     }
 
+    /// Links the templates of every engine to the fingerprinted assets
+    pub fn set_assets(&mut self, assets: Arc<AssetManifest>) {
+        for engine in &mut self.engines {
+            engine.register_assets(assets.clone());
+        }
+    }
+
+    /// Provides the message catalogs to the templates of every engine
+    pub fn set_messages(&mut self, messages: Messages) {
+        let messages = Arc::new(messages);
+        for engine in &mut self.engines {
+            engine.register_messages(messages.clone());
+        }
+    }
+
     #[instrument(level = "info", skip_all, fields(directory = directory.as_ref()))]
     pub fn register_directory<S>(&mut self, directory: S) -> Result<()>
     where
@@ -51,7 +102,23 @@ impl Registry {
             )
             .to_string();
 
-        self.visit_directory(&path, &[prefix])
+        let mut templates = BTreeMap::new();
+        self.visit_directory(&path, &[prefix], &mut templates)?;
+
+        for engine in &mut self.engines {
+            if let Some(templates) = templates.get(engine.extension()) {
+                engine.register(templates)?;
+            }
+        }
+
+        self.templates.extend(
+            templates
+                .into_values()
+                .flatten()
+                .map(|template| template.name),
+        );
+
+        Ok(())
     }
 
     pub fn has_metadata<S>(&self, page: S) -> bool
@@ -61,12 +128,10 @@ impl Registry {
         self.page_metatdata.contains_key(page.as_ref())
     }
 
-    pub fn default_metadata(&self) -> PageMetadata {
+    pub fn default_metadata(&self, locale: Option<&str>) -> PageMetadata {
         match &self.site_metadata {
             Some(site_metadata) => self
-                .page_metatdata
-                .get(site_metadata)
-                .cloned()
+                .find_localized_metadata(site_metadata, locale)
                 .unwrap_or_default(),
             None => PageMetadata::default(),
         }
@@ -79,18 +144,181 @@ impl Registry {
         self.page_metatdata.get(page.as_ref()).cloned()
     }
 
+    /// The metadata of the page with that of its variant in the locale over it, such
+    /// as the translated title of `content/donate.es.json`
+    pub fn find_localized_metadata(
+        &self,
+        page: &str,
+        locale: Option<&str>,
+    ) -> Option<PageMetadata> {
+        let variant = locale
+            .into_iter()
+            .flat_map(Self::variants)
+            .find_map(|locale| self.page_metatdata.get(&format!("{page}.{locale}")));
+
+        match (self.page_metatdata.get(page), variant) {
+            (Some(metadata), Some(variant)) => {
+                let mut metadata = metadata.clone();
+                metadata.overlay(variant);
+                Some(metadata)
+            }
+            (metadata, variant) => metadata.or(variant).cloned(),
+        }
+    }
+
+    /// The name of the page's variant in the locale, `templates/content/donate.es`,
+    /// or the page itself when there is no variant
+    pub fn localized(&self, page: &str, locale: Option<&str>) -> String {
+        locale
+            .into_iter()
+            .flat_map(Self::variants)
+            .map(|locale| format!("{page}.{locale}"))
+            .find(|variant| self.has_template(variant))
+            .unwrap_or_else(|| page.to_owned())
+    }
+
+    /// The locale followed by its language, `es-MX` then `es`
+    fn variants(locale: &str) -> Vec<&str> {
+        let mut variants = vec![locale];
+        if let Some((language, _)) = locale.split_once(['-', '_']) {
+            variants.push(language);
+        }
+        variants
+    }
+
+    /// The template of the error page for the status, `errors/{status}` falling back to
+    /// `errors/error` within any of the template directories
+    pub fn error_template(&self, status: StatusCode) -> Option<String> {
+        [
+            format!("errors/{}", status.as_u16()),
+            String::from("errors/error"),
+        ]
+        .iter()
+        .find_map(|error| {
+            self.templates
+                .iter()
+                .find(|name| *name == error || name.ends_with(&format!("/{error}")))
+                .cloned()
+        })
+    }
+
+    /// The sitemap entries declared by the page metadata
+    pub fn sitemap(&self) -> Vec<SitemapEntry> {
+        self.page_metatdata
+            .values()
+            .filter(|metadata| !metadata.is_noindex())
+            .filter_map(PageMetadata::sitemap)
+            .cloned()
+            .collect()
+    }
+
+    /// The path of each page template within a `content` directory, the path of its
+    /// sitemap metadata or its name within the directory so `content/news/list` is
+    /// `/news/list`. Locale variants and the pages excluded from the sitemap are left out.
+    pub fn pages(&self) -> Vec<String> {
+        self.templates
+            .iter()
+            .filter(|name| !name.rsplit('/').next().unwrap_or_default().contains('.'))
+            .filter_map(|name| {
+                let page = name
+                    .split('/')
+                    .skip_while(|segment| *segment != "content")
+                    .skip(1)
+                    .collect::<Vec<_>>();
+                if page.is_empty() {
+                    return None;
+                }
+
+                match self
+                    .page_metatdata
+                    .get(name)
+                    .and_then(PageMetadata::sitemap)
+                {
+                    Some(entry) if entry.is_excluded() => None,
+                    Some(entry) => Some(entry.path().to_owned()),
+                    None => Some(format!("/{}", page.join("/"))),
+                }
+            })
+            .collect()
+    }
+
     pub fn has_template<S>(&self, page: S) -> bool
     where
         S: AsRef<str>,
     {
-        self.handlebars.has_template(page.as_ref())
+        self.markdown.contains_key(page.as_ref()) || self.engine(page.as_ref()).is_some()
+    }
+
+    /// The layout declared by the front matter of a markdown page
+    pub fn markdown_layout<S>(&self, page: S) -> Option<&str>
+    where
+        S: AsRef<str>,
+    {
+        self.markdown.get(page.as_ref())?.layout()
+    }
+
+    /// Renders the page with the engine of its template, or the layout's when the page
+    /// has no template. Markdown pages render the layout from their front matter, or
+    /// the page's layout, with the html of the page as the `content` value.
+    pub fn render(
+        &self,
+        layout: &str,
+        template: Option<&str>,
+        values: &Map<String, Value>,
+    ) -> Result<String, PageError> {
+        let name = template.unwrap_or(layout);
+        if let Some(page) = self.markdown.get(name) {
+            let Some(layout) = page.layout().or(template.and(Some(layout))) else {
+                return Ok(page.html().to_owned());
+            };
+
+            let mut values = values.clone();
+            values.remove("content_template");
+            values.insert(String::from("content"), Value::from(page.html()));
+            values.insert(String::from("front_matter"), page.front_matter().clone());
+            return self.render(layout, None, &values);
+        }
+
+        let engine = self
+            .engine(name)
+            .ok_or_else(|| PageError::TemplateNotFound(layout.to_owned(), name.to_owned()))?;
+
+        // the layout has to be rendered by the same engine as the template
+        if !engine.has_template(layout) {
+            return Err(PageError::LayoutNotFound(layout.to_owned()));
+        }
+
+        engine.render(layout, template, values)
     }
 
-    pub fn handlebars<'h>(&'h self) -> &'h Handlebars<'h> {
-        &self.handlebars
+    /// Renders the template without its layout, for pages requested as a fragment
+    pub fn render_fragment(
+        &self,
+        template: &str,
+        values: &Map<String, Value>,
+    ) -> Result<String, PageError> {
+        if let Some(page) = self.markdown.get(template) {
+            return Ok(page.html().to_owned());
+        }
+
+        self.engine(template)
+            .ok_or_else(|| PageError::TemplateNotFound(String::new(), template.to_owned()))?
+            .render_fragment(template, values)
     }
 
-    fn visit_directory(&mut self, directory: &PathBuf, prefix: &[String]) -> Result<()> {
+    fn engine(&self, name: &str) -> Option<&dyn TemplateEngine> {
+        self.engines
+            .iter()
+            .find(|engine| engine.has_template(name))
+            .map(AsRef::as_ref)
+    }
+
+    fn visit_directory(
+        &mut self,
+        directory: &PathBuf,
+        prefix: &[String],
+        templates: &mut BTreeMap<String, Vec<TemplateFile>>,
+    ) -> Result<()> {
         for entry in read_dir(directory)
             .with_context(|| format!("failed to read directory {directory:?}"))?
         {
@@ -106,29 +334,58 @@ impl Registry {
                         .and_then(OsStr::to_str)
                         .map(ToOwned::to_owned),
                 );
-                self.visit_directory(&path.to_path_buf(), &prefix)?;
+                self.visit_directory(&path.to_path_buf(), &prefix, templates)?;
             } else if path.is_file() {
                 match entry.path().extension().and_then(OsStr::to_str) {
-                    Some("hbs") => {
-                        let name = Self::create_name(&entry, prefix)?;
-                        tracing::info!(
-                            path = ?entry.path().canonicalize().unwrap_or_else(|_| entry.path()),
-                            "registering template '{name}'"
-                        );
-                        self.handlebars
-                            .register_template_file(&name, &path)
-                            .with_context(|| format!("failed to register template '{name}]"))?;
-                    }
                     Some("json") => {
                         let name = Self::create_name(&entry, prefix)?;
                         tracing::info!(
                             path = ?entry.path().canonicalize().unwrap_or_else(|_| entry.path()),
                             "registering page metadata '{name}'"
                         );
-                        let page_metadata = PageMetadata::try_from(&entry.path())
+                        let mut page_metadata = PageMetadata::try_from(&entry.path())
                             .with_context(|| format!("failed to load page metadata '{name}"))?;
+                        // the front matter of a markdown page wins over its metadata file
+                        if let Some(page) = self.markdown.get(&name) {
+                            page_metadata.overlay(page.metadata());
+                        }
                         self.page_metatdata.insert(name, page_metadata);
                     }
+                    Some("md") => {
+                        let name = Self::create_name(&entry, prefix)?;
+                        tracing::info!(path = ?path, "registering markdown page '{name}'");
+                        let page = MarkdownPage::load(&path)?;
+                        self.page_metatdata
+                            .entry(name.clone())
+                            .or_default()
+                            .overlay(page.metadata());
+                        self.templates.insert(name.clone());
+                        self.markdown.insert(name, page);
+                    }
+                    Some(extension)
+                        if self
+                            .engines
+                            .iter()
+                            .any(|engine| engine.extension() == extension) =>
+                    {
+                        let name = Self::create_name(&entry, prefix)?;
+                        let relative_path = prefix
+                            .iter()
+                            .skip(1)
+                            .map(String::as_str)
+                            .chain(entry.file_name().to_str())
+                            .collect::<Vec<&str>>()
+                            .join("/");
+
+                        templates
+                            .entry(extension.to_owned())
+                            .or_default()
+                            .push(TemplateFile {
+                                name,
+                                relative_path,
+                                path: path.canonicalize().unwrap_or(path),
+                            });
+                    }
                     _ => {}
                 }
             }
@@ -152,6 +409,158 @@ impl Registry {
     }
 }
 
+#[cfg(all(test, feature = "tera"))]
+mod tests {
+    use super::*;
+    use crate::template_engine::TeraEngine;
+    use crate::test_templates::TestTemplates;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn render_with_engines() {
+        let templates = TestTemplates::new(&[
+            (
+                "layout/main.tera",
+                "{{ title }}:{% block content %}{% endblock content %}",
+            ),
+            (
+                "content/news.tera",
+                "{% extends \"layout/main.tera\" %}{% block content %}{{ news }}{% endblock content %}",
+            ),
+            (
+                "layout/admin.hbs",
+                "{{title}}:{{> (lookup this \"content_template\")}}",
+            ),
+            ("content/users.hbs", "{{users}}"),
+        ]);
+
+        let registry = templates.registry(vec![Box::new(TeraEngine::new())]);
+        assert!(registry.has_template("templates/layout/main"));
+        assert!(registry.has_template("templates/content/users"));
+
+        let values = json!({"title": "Site", "news": "today", "users": 2, "content_template": "templates/content/users"});
+        let values = values.as_object().unwrap();
+        assert_eq!(
+            "Site:today",
+            registry
+                .render(
+                    "templates/layout/main",
+                    Some("templates/content/news"),
+                    values
+                )
+                .unwrap()
+        );
+        assert_eq!(
+            "Site:2",
+            registry
+                .render(
+                    "templates/layout/admin",
+                    Some("templates/content/users"),
+                    values
+                )
+                .unwrap()
+        );
+        assert!(
+            registry
+                .render(
+                    "templates/layout/admin",
+                    Some("templates/content/news"),
+                    values
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn render_markdown() {
+        let templates = TestTemplates::new(&[
+            (
+                "layout/markdown.tera",
+                "{{ front_matter.title | default(value=\"\") }}:{{ content | safe }}",
+            ),
+            (
+                "content/donate.md",
+                "---
+title: Donate
+layout: templates/layout/markdown
+sitemap:
+  path: /donate
+---
+Give *generously*
+",
+            ),
+            (
+                "content/donate.json",
+                r#"{"title": "Other", "sitemap": {"path": "/give"}, "robots": ["nofollow"]}"#,
+            ),
+            ("content/host.md", "Host *us*"),
+            ("content/donate.es.md", "---\ntitle: Donar\n---\nDonar"),
+        ]);
+
+        let registry = templates.registry(vec![Box::new(TeraEngine::new())]);
+        assert!(registry.has_template("templates/content/donate"));
+        assert_eq!(
+            Some("templates/layout/markdown"),
+            registry.markdown_layout("templates/content/donate")
+        );
+        assert_eq!(
+            vec![SitemapEntry::builder().path("/donate").build()],
+            registry.sitemap()
+        );
+
+        let values = Map::new();
+        assert_eq!(
+            "Donate:<p>Give <em>generously</em></p>\n",
+            registry
+                .render(
+                    "templates/layout/main",
+                    Some("templates/content/donate"),
+                    &values
+                )
+                .unwrap()
+        );
+        assert_eq!(
+            "<p>Give <em>generously</em></p>\n",
+            registry
+                .render_fragment("templates/content/donate", &values)
+                .unwrap()
+        );
+        assert_eq!(
+            ":<p>Host <em>us</em></p>",
+            registry
+                .render(
+                    "templates/layout/markdown",
+                    Some("templates/content/host"),
+                    &values
+                )
+                .unwrap()
+        );
+
+        assert_eq!(
+            "templates/content/donate.es",
+            registry.localized("templates/content/donate", Some("es-MX"))
+        );
+        assert_eq!(
+            "templates/content/donate",
+            registry.localized("templates/content/donate", Some("fr"))
+        );
+        assert_eq!(
+            "templates/content/host",
+            registry.localized("templates/content/host", Some("es"))
+        );
+
+        let mut values = Map::new();
+        registry
+            .find_localized_metadata("templates/content/donate", Some("es"))
+            .unwrap()
+            .apply(&mut values)
+            .unwrap();
+        assert_eq!(Some(&json!("Donar")), values.get("title"));
+        assert_eq!(Some(&json!("nofollow")), values.get("robots"));
+    }
+}
+
 /*
 
 fn register_directory(
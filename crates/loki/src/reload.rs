@@ -0,0 +1,299 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+
+/// Something rebuilt from the files on disk when they change during development
+pub trait Reload: Send + Sync {
+    /// Rebuilds from the files on disk, the current value is kept when this fails
+    fn reload(&self) -> Result<()>;
+
+    /// The error from the last reload if it failed
+    fn error(&self) -> Option<String>;
+}
+
+type Load<T> = Arc<dyn Fn() -> Result<T> + Send + Sync>;
+
+///
+/// Holds a value loaded from disk, such as a template registry, which can be rebuilt
+/// while the application is running. Callers take a snapshot with [Reloadable::get]
+/// so a reload never changes the value underneath a request.
+///
+pub struct Reloadable<T> {
+    current: Arc<RwLock<Arc<T>>>,
+    error: Arc<RwLock<Option<String>>>,
+    load: Load<T>,
+}
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current.clone(),
+            error: self.error.clone(),
+            load: self.load.clone(),
+        }
+    }
+}
+
+impl<T> Reloadable<T> {
+    /// Loads the initial value, unlike a reload a failure here is returned
+    pub fn new<F>(load: F) -> Result<Self>
+    where
+        F: Fn() -> Result<T> + Send + Sync + 'static,
+    {
+        let value = load()?;
+        Ok(Self {
+            current: Arc::new(RwLock::new(Arc::new(value))),
+            error: Arc::new(RwLock::new(None)),
+            load: Arc::new(load),
+        })
+    }
+
+    /// The current value
+    pub fn get(&self) -> Arc<T> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn set_error(&self, error: Option<String>) {
+        match self.error.write() {
+            Ok(mut current) => *current = error,
+            Err(poisoned) => *poisoned.into_inner() = error,
+        }
+    }
+}
+
+impl<T> Reload for Reloadable<T>
+where
+    T: Send + Sync,
+{
+    fn reload(&self) -> Result<()> {
+        match (self.load)() {
+            Ok(value) => {
+                match self.current.write() {
+                    Ok(mut current) => *current = Arc::new(value),
+                    Err(poisoned) => *poisoned.into_inner() = Arc::new(value),
+                }
+                self.set_error(None);
+                Ok(())
+            }
+            Err(e) => {
+                self.set_error(Some(format!("{e:#}")));
+                Err(e)
+            }
+        }
+    }
+
+    fn error(&self) -> Option<String> {
+        match self.error.read() {
+            Ok(error) => error.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+#[cfg(all(debug_assertions, feature = "hot-reload"))]
+pub(crate) mod watch {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::{Context, Result};
+    use axum::{
+        Router,
+        extract::{Request, State},
+        http::StatusCode,
+        middleware::{self, Next},
+        response::{Html, IntoResponse, Response},
+    };
+    use notify::{RecursiveMode, Watcher};
+    use tokio::sync::mpsc;
+
+    use super::Reload;
+    use crate::html::escape;
+    use crate::invalidation::Invalidate;
+
+    type Reloads = Arc<Vec<Arc<dyn Reload>>>;
+
+    /// Watches the directories reloading everything when a file changes, the caches
+    /// are cleared after each reload so they don't serve pages rendered with the
+    /// previous templates. Failed reloads are shown in the browser until fixed.
+    pub(crate) fn hot_reload(
+        routes: Router,
+        directories: &[String],
+        reloads: Vec<Arc<dyn Reload>>,
+        caches: Vec<&'static dyn Invalidate>,
+    ) -> Result<Router> {
+        let (sender, mut changes) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if let Ok(event) = event
+                    && !event.kind.is_access()
+                {
+                    let _ = sender.send(event.paths);
+                }
+            })
+            .with_context(|| "failed to create the template watcher")?;
+
+        for directory in directories {
+            watcher
+                .watch(Path::new(directory), RecursiveMode::Recursive)
+                .with_context(|| format!("failed to watch '{directory}'"))?;
+            tracing::info!("watching '{directory}' for changes");
+        }
+
+        let reloads: Reloads = Arc::new(reloads);
+        let watched = reloads.clone();
+        tokio::spawn(async move {
+            // the watcher stops when dropped
+            let _watcher = watcher;
+            while let Some(paths) = changes.recv().await {
+                // editors often write a file in several steps, wait for them to settle
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                while changes.try_recv().is_ok() {}
+
+                tracing::info!(changed = ?paths, "reloading templates");
+                let reloads = watched.clone();
+                let reloaded = tokio::task::spawn_blocking(move || {
+                    reloads
+                        .iter()
+                        .map(|reload| reload.reload())
+                        .filter_map(Result::err)
+                        .inspect(|e| tracing::error!("failed to reload: {e:#}"))
+                        .count()
+                        == 0
+                })
+                .await
+                .unwrap_or(false);
+
+                if reloaded {
+                    for cache in &caches {
+                        cache.invalidate().await;
+                    }
+                }
+            }
+        });
+
+        Ok(routes.layer(middleware::from_fn_with_state(reloads, report_errors)))
+    }
+
+    async fn report_errors(
+        State(reloads): State<Reloads>,
+        request: Request,
+        next: Next,
+    ) -> Response {
+        let errors = reloads
+            .iter()
+            .filter_map(|reload| reload.error())
+            .map(|error| format!("<pre>{}</pre>", escape(&error)))
+            .collect::<String>();
+
+        if errors.is_empty() {
+            return next.run(request).await;
+        }
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Html(format!(
+                "<!DOCTYPE html><html><head><title>Template Error</title></head>\
+                <body><h1>Failed to reload the templates</h1>{errors}</body></html>"
+            )),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use anyhow::anyhow;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reload_keeps_value_on_failure() {
+        static LOADS: AtomicUsize = AtomicUsize::new(0);
+        let reloadable = Reloadable::new(|| match LOADS.fetch_add(1, Ordering::SeqCst) {
+            1 => Err(anyhow!("syntax error")),
+            load => Ok(load),
+        })
+        .unwrap();
+        assert_eq!(0, *reloadable.get());
+
+        assert!(reloadable.reload().is_err());
+        assert_eq!(0, *reloadable.get());
+        assert_eq!(Some(String::from("syntax error")), reloadable.error());
+
+        reloadable.reload().unwrap();
+        assert_eq!(2, *reloadable.get());
+        assert_eq!(None, reloadable.error());
+    }
+
+    #[cfg(all(debug_assertions, feature = "hot-reload"))]
+    #[tokio::test]
+    async fn hot_reload() {
+        use axum::body::{Body, to_bytes};
+        use axum::http::{Request, StatusCode};
+        use axum::routing::get;
+        use std::time::Duration;
+        use tower::ServiceExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let template = directory.join("page.txt");
+        std::fs::write(&template, "first").unwrap();
+
+        let reloadable = Reloadable::new({
+            let template = template.clone();
+            move || {
+                let text = std::fs::read_to_string(&template)?;
+                if text.contains("{{") {
+                    return Err(anyhow!("unclosed tag in {template:?}"));
+                }
+                Ok(text)
+            }
+        })
+        .unwrap();
+
+        let page = reloadable.clone();
+        let routes = axum::Router::new().route(
+            "/",
+            get(move || {
+                let page = page.clone();
+                async move { page.get().to_string() }
+            }),
+        );
+        let routes = watch::hot_reload(
+            routes,
+            &[directory.to_string_lossy().to_string()],
+            vec![Arc::new(reloadable.clone())],
+            vec![],
+        )
+        .unwrap();
+
+        let request = |routes: axum::Router| async move {
+            let response = routes
+                .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        };
+
+        std::fs::write(&template, "second").unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(
+            (StatusCode::OK, String::from("second")),
+            request(routes.clone()).await
+        );
+
+        std::fs::write(&template, "{{ broken").unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let (status, body) = request(routes.clone()).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert!(body.contains("unclosed tag"), "{body}");
+    }
+}
@@ -1,20 +1,86 @@
 use std::{net::SocketAddr, time::Duration};
 
 use anyhow::{Context, Result};
-use axum::{Router, http::Request};
+use axum::{
+    Router,
+    http::{Extensions, HeaderMap, Request, StatusCode, Version, header},
+};
+use bon::Builder;
 use tokio::{
     net::TcpListener,
     signal::unix::{SignalKind, signal},
 };
 use tower::ServiceBuilder;
-use tower_http::{timeout::TimeoutLayer, trace::TraceLayer};
+use tower_http::{
+    compression::{CompressionLayer, Predicate, predicate::SizeAbove},
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
+};
 use tracing::Span;
 
+///
+/// Compresses responses with gzip, brotli or zstd, whichever the client prefers. Only
+/// responses of the content types which are at least the minimum size are compressed,
+/// small responses can grow and most media is compressed already. Responses with an
+/// encoding, such as precompressed assets, are left as they are.
+///
+#[derive(Debug, Clone, Builder)]
+pub struct Compression {
+    /// The smallest response compressed in bytes, responses of an unknown size are
+    /// always compressed
+    #[builder(default = 1024)]
+    min_size: u16,
+    /// The content types compressed, without their parameters such as the charset
+    #[builder(default = Compression::default_content_types())]
+    content_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl Compression {
+    fn default_content_types() -> Vec<String> {
+        [
+            "text/html",
+            "text/css",
+            "text/plain",
+            "text/javascript",
+            "text/xml",
+            "application/javascript",
+            "application/json",
+            "application/xml",
+            "image/svg+xml",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    fn predicate(&self) -> impl Predicate + use<> {
+        let content_types = self.content_types.clone();
+        SizeAbove::new(self.min_size).and(
+            move |_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+                headers
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|content_type| content_type.to_str().ok())
+                    .and_then(|content_type| content_type.split(';').next())
+                    .is_some_and(|content_type| {
+                        content_types
+                            .iter()
+                            .any(|allowed| allowed.eq_ignore_ascii_case(content_type.trim()))
+                    })
+            },
+        )
+    }
+}
+
 pub struct Server;
 
 impl Server {
-    pub async fn serve(port: u16, app: Router) -> Result<()> {
-        let app = Self::apply_layers(app);
+    pub async fn serve(port: u16, app: Router, compression: Compression) -> Result<()> {
+        let app = Self::apply_layers(app, &compression);
 
         let address = SocketAddr::from(([0, 0, 0, 0], port));
         let listener = TcpListener::bind(address)
@@ -36,7 +102,7 @@ impl Server {
         Ok(())
     }
 
-    fn apply_layers(app: Router) -> Router {
+    fn apply_layers(app: Router, compression: &Compression) -> Router {
         app.layer(
             ServiceBuilder::new()
                 .layer(
@@ -52,7 +118,8 @@ impl Server {
                             tracing::debug!("request {} '{}'", request.method(), request.uri());
                         }),
                 )
-                .layer(TimeoutLayer::new(Duration::from_secs(45))),
+                .layer(TimeoutLayer::new(Duration::from_secs(45)))
+                .layer(CompressionLayer::new().compress_when(compression.predicate())),
         )
     }
 
@@ -75,3 +142,82 @@ impl Server {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt;
+
+    async fn encoding(app: &Router, path: &str, accept: &str) -> Option<String> {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(path)
+                    .header(header::ACCEPT_ENCODING, accept)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|encoding| encoding.to_str().unwrap().to_owned())
+    }
+
+    #[tokio::test]
+    async fn compress_responses() {
+        let page = "<p>loki</p>".repeat(200);
+        let app =
+            Router::new()
+                .route(
+                    "/page",
+                    get({
+                        let page = page.clone();
+                        move || async move {
+                            ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], page)
+                        }
+                    }),
+                )
+                .route(
+                    "/small",
+                    get(|| async { ([(header::CONTENT_TYPE, "text/html")], "<p>loki</p>") }),
+                )
+                .route(
+                    "/image",
+                    get(move || async move { ([(header::CONTENT_TYPE, "image/png")], page) }),
+                );
+        let app = Server::apply_layers(app, &Compression::default());
+
+        assert_eq!(
+            Some(String::from("gzip")),
+            encoding(&app, "/page", "gzip").await
+        );
+        assert_eq!(
+            Some(String::from("br")),
+            encoding(&app, "/page", "gzip;q=0.5, br").await
+        );
+        assert_eq!(
+            Some(String::from("zstd")),
+            encoding(&app, "/page", "zstd").await
+        );
+        assert_eq!(None, encoding(&app, "/page", "identity").await);
+        assert_eq!(None, encoding(&app, "/small", "gzip").await);
+        assert_eq!(None, encoding(&app, "/image", "gzip").await);
+
+        let app = Server::apply_layers(
+            Router::new().route(
+                "/small",
+                get(|| async { ([(header::CONTENT_TYPE, "text/html")], "<p>loki</p>") }),
+            ),
+            &Compression::builder().min_size(0).build(),
+        );
+        assert_eq!(
+            Some(String::from("gzip")),
+            encoding(&app, "/small", "gzip").await
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Router,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use bon::Builder;
+use chrono::NaiveDate;
+use futures::future::{BoxFuture, join_all};
+use serde::Deserialize;
+
+use crate::{html::escape, registry::Registry, reload::Reloadable};
+
+///
+/// A url listed in `/sitemap.xml`, pages declare theirs in the `sitemap` section of
+/// their metadata while urls which don't map to a template, such as individual
+/// articles, are provided by the application. Excluded pages are left out of the
+/// sitemap and disallowed in `/robots.txt`.
+///
+/// ```json
+/// { "sitemap": { "path": "/news", "changefreq": "daily", "priority": 0.8 } }
+/// ```
+///
+#[derive(Builder, Deserialize, Debug, Clone, PartialEq)]
+#[builder(on(String, into))]
+pub struct SitemapEntry {
+    /// The path of the page, or an absolute url
+    path: String,
+    lastmod: Option<NaiveDate>,
+    priority: Option<f32>,
+    changefreq: Option<String>,
+    #[builder(default)]
+    #[serde(default)]
+    exclude: bool,
+}
+
+impl SitemapEntry {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn is_excluded(&self) -> bool {
+        self.exclude
+    }
+}
+
+/// Provides the urls the templates don't know about, such as those stored in the
+/// database
+pub type SitemapFn = Arc<dyn Fn() -> BoxFuture<'static, Vec<SitemapEntry>> + Send + Sync>;
+
+/// The rules served in `/robots.txt`, the excluded pages and a link to the sitemap
+/// are added to them
+#[derive(Builder, Debug, Clone)]
+#[builder(on(String, into))]
+pub struct Robots {
+    #[builder(default = String::from("*"))]
+    user_agent: String,
+    #[builder(default)]
+    allow: Vec<String>,
+    #[builder(default)]
+    disallow: Vec<String>,
+}
+
+impl Default for Robots {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Sitemap {
+    registry: Reloadable<Registry>,
+    providers: Vec<SitemapFn>,
+    /// The url the site is served from, without it the url is taken from the request
+    /// which should only be relied on during development
+    site_url: Option<String>,
+    robots: Robots,
+}
+
+impl Sitemap {
+    pub(crate) fn new(
+        registry: Reloadable<Registry>,
+        providers: Vec<SitemapFn>,
+        site_url: Option<String>,
+        robots: Robots,
+    ) -> Self {
+        Self {
+            registry,
+            providers,
+            site_url,
+            robots,
+        }
+    }
+
+    pub(crate) async fn entries(&self) -> Vec<SitemapEntry> {
+        let mut entries = self.registry.get().sitemap();
+        entries.extend(
+            join_all(self.providers.iter().map(|provider| provider()))
+                .await
+                .into_iter()
+                .flatten(),
+        );
+        entries
+    }
+
+    fn site_url(&self, headers: &HeaderMap) -> String {
+        if let Some(site_url) = &self.site_url {
+            return site_url.trim_end_matches('/').to_owned();
+        }
+
+        let host = headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or("localhost");
+        let scheme = headers
+            .get("x-forwarded-proto")
+            .and_then(|scheme| scheme.to_str().ok())
+            .unwrap_or("http");
+        format!("{scheme}://{host}")
+    }
+
+    fn url(site_url: &str, path: &str) -> String {
+        if path.starts_with("http://") || path.starts_with("https://") {
+            path.to_owned()
+        } else {
+            format!("{site_url}/{}", path.trim_start_matches('/'))
+        }
+    }
+
+    async fn sitemap_xml(&self, headers: &HeaderMap) -> String {
+        let site_url = self.site_url(headers);
+        let mut urls = self
+            .entries()
+            .await
+            .into_iter()
+            .filter(|entry| !entry.exclude)
+            .collect::<Vec<_>>();
+        urls.sort_by(|a, b| a.path.cmp(&b.path));
+        urls.dedup_by(|a, b| a.path == b.path);
+
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+        for entry in urls {
+            xml.push_str("  <url>\n");
+            xml.push_str(&format!(
+                "    <loc>{}</loc>\n",
+                escape(&Self::url(&site_url, &entry.path))
+            ));
+            if let Some(lastmod) = entry.lastmod {
+                xml.push_str(&format!("    <lastmod>{lastmod}</lastmod>\n"));
+            }
+            if let Some(changefreq) = &entry.changefreq {
+                xml.push_str(&format!(
+                    "    <changefreq>{}</changefreq>\n",
+                    escape(changefreq)
+                ));
+            }
+            if let Some(priority) = entry.priority {
+                xml.push_str(&format!(
+                    "    <priority>{:.1}</priority>\n",
+                    priority.clamp(0.0, 1.0)
+                ));
+            }
+            xml.push_str("  </url>\n");
+        }
+        xml.push_str("</urlset>\n");
+        xml
+    }
+
+    async fn robots_txt(&self, headers: &HeaderMap) -> String {
+        let mut disallow = self.robots.disallow.clone();
+        disallow.extend(
+            self.entries()
+                .await
+                .into_iter()
+                .filter(|entry| entry.exclude)
+                .map(|entry| entry.path),
+        );
+        disallow.sort();
+        disallow.dedup();
+
+        let mut robots = format!("User-agent: {}\n", self.robots.user_agent);
+        for path in &self.robots.allow {
+            robots.push_str(&format!("Allow: {path}\n"));
+        }
+        for path in &disallow {
+            robots.push_str(&format!("Disallow: {path}\n"));
+        }
+        robots.push_str(&format!(
+            "\nSitemap: {}/sitemap.xml\n",
+            self.site_url(headers)
+        ));
+        robots
+    }
+}
+
+/// Serves `/sitemap.xml` and `/robots.txt`
+pub(crate) fn sitemap_routes(sitemap: Sitemap) -> Router {
+    Router::new()
+        .route("/sitemap.xml", get(sitemap_xml))
+        .route("/robots.txt", get(robots_txt))
+        .layer(Extension(Arc::new(sitemap)))
+}
+
+async fn sitemap_xml(Extension(sitemap): Extension<Arc<Sitemap>>, headers: HeaderMap) -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/xml")],
+        sitemap.sitemap_xml(&headers).await,
+    )
+        .into_response()
+}
+
+async fn robots_txt(Extension(sitemap): Extension<Arc<Sitemap>>, headers: HeaderMap) -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain")],
+        sitemap.robots_txt(&headers).await,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_templates::TestTemplates;
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use futures::FutureExt;
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt;
+
+    fn routes() -> Router {
+        let templates = TestTemplates::new(&[
+            ("content/home.hbs", "home"),
+            (
+                "content/home.json",
+                r#"{"sitemap": {"path": "/", "priority": 1.0, "lastmod": "2025-06-01"}}"#,
+            ),
+            ("content/admin.hbs", "admin"),
+            (
+                "content/admin.json",
+                r#"{"sitemap": {"path": "/admin", "exclude": true}}"#,
+            ),
+            ("content/draft.hbs", "draft"),
+            (
+                "content/draft.json",
+                r#"{"robots": ["noindex"], "sitemap": {"path": "/draft"}}"#,
+            ),
+        ]);
+        sitemap_routes(Sitemap::new(
+            templates.reloadable(),
+            vec![Arc::new(|| {
+                async {
+                    vec![
+                        SitemapEntry::builder()
+                            .path("/news/1?a=1&b=2")
+                            .changefreq("daily")
+                            .build(),
+                    ]
+                }
+                .boxed()
+            })],
+            Some(String::from("https://example.com/")),
+            Robots::builder()
+                .disallow(vec![String::from("/api")])
+                .build(),
+        ))
+    }
+
+    async fn request(routes: Router, uri: &str) -> String {
+        let response = routes
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sitemap() {
+        let routes = routes();
+
+        assert_eq!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n  \
+              <url>\n    \
+                <loc>https://example.com/</loc>\n    \
+                <lastmod>2025-06-01</lastmod>\n    \
+                <priority>1.0</priority>\n  \
+              </url>\n  \
+              <url>\n    \
+                <loc>https://example.com/news/1?a=1&amp;b=2</loc>\n    \
+                <changefreq>daily</changefreq>\n  \
+              </url>\n\
+            </urlset>\n",
+            request(routes.clone(), "/sitemap.xml").await
+        );
+
+        assert_eq!(
+            "User-agent: *\n\
+            Disallow: /admin\n\
+            Disallow: /api\n\
+            \n\
+            Sitemap: https://example.com/sitemap.xml\n",
+            request(routes, "/robots.txt").await
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde_json::{Map, Value};
+
+use crate::PageError;
+use crate::assets::AssetManifest;
+use crate::i18n::Messages;
+
+mod handlebars_engine;
+pub(crate) mod helpers;
+#[cfg(feature = "tera")]
+mod tera_engine;
+
+pub use handlebars_engine::HandlebarsEngine;
+#[cfg(feature = "tera")]
+pub use tera_engine::TeraEngine;
+
+/// Creates the engine each time the templates are loaded
+pub type TemplateEngineFn = Arc<dyn Fn() -> Box<dyn TemplateEngine> + Send + Sync>;
+
+/// A template file found within one of the template directories
+#[derive(Debug, Clone)]
+pub struct TemplateFile {
+    /// The name pages use for the template, this is the path without the extension
+    /// prefixed by the template directory, `templates/layout/main`
+    pub name: String,
+    /// The path relative to the template directory, engines where templates reference
+    /// each other by path (`{% extends "layout/main.tera" %}`) use this name instead
+    pub relative_path: String,
+    pub path: PathBuf,
+}
+
+///
+/// Renders the templates with a given extension, the registry can hold several
+/// engines and picks the one which registered the page's template. Engines are
+/// created by the application so they can be configured with helpers or filters.
+///
+pub trait TemplateEngine: Send + Sync {
+    /// The extension of the templates rendered by the engine, without the dot
+    fn extension(&self) -> &'static str;
+
+    /// Registers the templates of a directory at once, engines supporting
+    /// inheritance need every template to resolve the parents
+    fn register(&mut self, templates: &[TemplateFile]) -> Result<()>;
+
+    fn has_template(&self, name: &str) -> bool;
+
+    /// Provides the message catalogs to the engine's `t` helper
+    fn register_messages(&mut self, _messages: Arc<Messages>) {}
+
+    /// Provides the fingerprinted urls to the engine's `asset` helper
+    fn register_assets(&mut self, _assets: Arc<AssetManifest>) {}
+
+    /// Renders a page, how the template is combined with the layout depends on the
+    /// engine. The values include the merged page metadata. Failures are reported as
+    /// [PageError::Render] or [PageError::MissingPartial].
+    fn render(
+        &self,
+        layout: &str,
+        template: Option<&str>,
+        values: &Map<String, Value>,
+    ) -> Result<String, PageError>;
+
+    /// Renders the template alone, without the layout, by default the template is
+    /// rendered as if it were the layout
+    fn render_fragment(
+        &self,
+        template: &str,
+        values: &Map<String, Value>,
+    ) -> Result<String, PageError> {
+        self.render(template, None, values)
+    }
+}
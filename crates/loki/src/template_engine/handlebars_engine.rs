@@ -0,0 +1,445 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use handlebars::{
+    Handlebars, Helper, HelperDef, RenderContext, RenderError, RenderErrorReason, ScopedJson,
+};
+use serde_json::{Map, Value};
+
+use super::{TemplateEngine, TemplateFile, helpers};
+use crate::PageError;
+use crate::assets::AssetManifest;
+use crate::i18n::Messages;
+
+///
+/// Renders `.hbs` templates, the layout is rendered with the name of the page's
+/// template in `content_template` which the layout includes as a partial
+///
+/// ```handlebars
+/// {{#if content_template}}
+///     {{> (lookup this "content_template")}}
+/// {{/if}}
+/// ```
+///
+/// The standard helpers are registered by every engine
+///
+/// ```handlebars
+/// {{{markdown notes}}}
+/// {{format_date published format="%d %b %Y" timezone="America/Boise"}}
+/// {{relative_time published}}
+/// {{count}} {{pluralize count "person" "people"}}
+/// {{truncate summary 120 end="..."}}
+/// <a href="{{url "/news" id page=2}}">
+/// <link href="{{asset "css/site.css"}}" rel="stylesheet">
+/// ```
+///
+pub struct HandlebarsEngine {
+    handlebars: Handlebars<'static>,
+}
+
+impl Default for HandlebarsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HandlebarsEngine {
+    pub fn new() -> Self {
+        let mut handlebars = Handlebars::new();
+        register_helpers(&mut handlebars);
+        Self { handlebars }
+    }
+
+    /// Access to the underlying registry to add helpers
+    pub fn handlebars_mut(&mut self) -> &mut Handlebars<'static> {
+        &mut self.handlebars
+    }
+}
+
+impl TemplateEngine for HandlebarsEngine {
+    fn extension(&self) -> &'static str {
+        "hbs"
+    }
+
+    fn register(&mut self, templates: &[TemplateFile]) -> Result<()> {
+        for template in templates {
+            tracing::info!(
+                path = ?template.path,
+                "registering template '{}'",
+                template.name
+            );
+            self.handlebars
+                .register_template_file(&template.name, &template.path)
+                .with_context(|| format!("failed to register template '{}'", template.name))?;
+        }
+
+        Ok(())
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.handlebars.has_template(name)
+    }
+
+    fn register_messages(&mut self, messages: Arc<Messages>) {
+        self.handlebars
+            .register_helper("t", Box::new(TranslateHelper(messages.clone())));
+        self.handlebars
+            .register_helper("localized_url", Box::new(LocalizedUrlHelper(messages)));
+    }
+
+    fn register_assets(&mut self, assets: Arc<AssetManifest>) {
+        self.handlebars.register_helper(
+            "asset",
+            Box::new(StandardHelper({
+                let assets = assets.clone();
+                move |helper: &Helper| Ok(Value::String(assets.url(str_param(helper, 0)?)))
+            })),
+        );
+        // `{{#each (bundle "css/main.css")}}<link href="{{this}}">{{/each}}`
+        self.handlebars.register_helper(
+            "bundle",
+            Box::new(StandardHelper(move |helper: &Helper| {
+                let name = str_param(helper, 0)?;
+                let urls = assets
+                    .bundle_urls(name)
+                    .ok_or_else(|| format!("there is no bundle named '{name}'"))?;
+                Ok(Value::from(urls))
+            })),
+        );
+    }
+
+    fn render(
+        &self,
+        layout: &str,
+        _template: Option<&str>,
+        values: &Map<String, Value>,
+    ) -> Result<String, PageError> {
+        self.handlebars.render(layout, values).map_err(|e| {
+            let template = e.template_name.clone().unwrap_or_else(|| layout.to_owned());
+            match e.reason() {
+                RenderErrorReason::PartialNotFound(partial) => PageError::MissingPartial {
+                    template,
+                    partial: partial.clone(),
+                },
+                reason => PageError::Render {
+                    template,
+                    line: e.line_no,
+                    message: reason.to_string(),
+                },
+            }
+        })
+    }
+}
+
+/// `{{t "nav.donate"}}` looks up the message in the page's `locale`, or the locale
+/// given as `{{t "nav.donate" locale="es"}}`
+struct TranslateHelper(Arc<Messages>);
+
+impl HelperDef for TranslateHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        context: &'rc handlebars::Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let key = helper
+            .param(0)
+            .and_then(|key| key.value().as_str())
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("t", 0))?;
+        let locale = helper
+            .hash_get("locale")
+            .and_then(|locale| locale.value().as_str())
+            .or_else(|| context.data().get("locale").and_then(Value::as_str));
+
+        Ok(ScopedJson::Derived(Value::String(
+            self.0.translate(locale, key),
+        )))
+    }
+}
+
+/// `{{localized_url "/news"}}` links to the page in the page's `locale`, or the locale
+/// given as `{{localized_url "/news" locale="es"}}`
+struct LocalizedUrlHelper(Arc<Messages>);
+
+impl HelperDef for LocalizedUrlHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        context: &'rc handlebars::Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let path = helper
+            .param(0)
+            .and_then(|path| path.value().as_str())
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("localized_url", 0))?;
+        let locale = helper
+            .hash_get("locale")
+            .and_then(|locale| locale.value().as_str())
+            .or_else(|| context.data().get("locale").and_then(Value::as_str));
+
+        let path = self.0.localized_path(locale, path);
+        Ok(ScopedJson::Derived(Value::String(helpers::url(
+            &path,
+            [],
+            [],
+        ))))
+    }
+}
+
+fn register_helpers(handlebars: &mut Handlebars<'static>) {
+    handlebars.register_helper(
+        "markdown",
+        Box::new(StandardHelper(|helper: &Helper| {
+            helpers::markdown(str_param(helper, 0)?).map(Value::String)
+        })),
+    );
+    handlebars.register_helper(
+        "format_date",
+        Box::new(StandardHelper(|helper: &Helper| {
+            helpers::format_date(
+                param(helper, 0)?,
+                hash_str(helper, "format"),
+                hash_str(helper, "timezone"),
+            )
+            .map(Value::String)
+        })),
+    );
+    handlebars.register_helper(
+        "relative_time",
+        Box::new(StandardHelper(|helper: &Helper| {
+            helpers::relative_time(param(helper, 0)?, Utc::now()).map(Value::String)
+        })),
+    );
+    handlebars.register_helper(
+        "pluralize",
+        Box::new(StandardHelper(|helper: &Helper| {
+            let count = param(helper, 0)?
+                .as_f64()
+                .ok_or("the count must be a number")?;
+            let plural = helper.param(2).and_then(|plural| plural.value().as_str());
+            Ok(Value::String(helpers::pluralize(
+                count,
+                str_param(helper, 1)?,
+                plural,
+            )))
+        })),
+    );
+    handlebars.register_helper(
+        "truncate",
+        Box::new(StandardHelper(|helper: &Helper| {
+            let length = param(helper, 1)?
+                .as_u64()
+                .ok_or("the length must be a number")?;
+            Ok(Value::String(helpers::truncate(
+                str_param(helper, 0)?,
+                length as usize,
+                hash_str(helper, "end"),
+            )))
+        })),
+    );
+    handlebars.register_helper(
+        "url",
+        Box::new(StandardHelper(|helper: &Helper| {
+            let segments = helper.params().iter().skip(1).map(|param| param.value());
+            let query = helper
+                .hash()
+                .iter()
+                .map(|(name, value)| (*name, value.value()));
+            Ok(Value::String(helpers::url(
+                str_param(helper, 0)?,
+                segments,
+                query,
+            )))
+        })),
+    );
+    handlebars.register_helper(
+        "asset",
+        Box::new(StandardHelper(|helper: &Helper| {
+            Ok(Value::String(helpers::asset_url(str_param(helper, 0)?)))
+        })),
+    );
+}
+
+/// Adapts one of the standard [helpers] to handlebars, failures are reported as
+/// render errors of the template
+struct StandardHelper<F>(F);
+
+impl<F> HelperDef for StandardHelper<F>
+where
+    F: Fn(&Helper) -> Result<Value, String> + Send + Sync,
+{
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc handlebars::Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        (self.0)(helper).map(ScopedJson::Derived).map_err(|e| {
+            RenderErrorReason::Other(format!("`{}` failed: {e}", helper.name())).into()
+        })
+    }
+}
+
+fn param<'a>(helper: &'a Helper, index: usize) -> Result<&'a Value, String> {
+    helper
+        .param(index)
+        .map(|param| param.value())
+        .ok_or_else(|| format!("missing parameter {index}"))
+}
+
+fn str_param<'a>(helper: &'a Helper, index: usize) -> Result<&'a str, String> {
+    param(helper, index)?
+        .as_str()
+        .ok_or_else(|| format!("parameter {index} must be a string"))
+}
+
+fn hash_str<'a>(helper: &'a Helper, name: &str) -> Option<&'a str> {
+    helper
+        .hash_get(name)
+        .and_then(|value| value.value().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn render(layout: &str, partial: Option<&str>) -> Result<String, PageError> {
+        let mut engine = HandlebarsEngine::new();
+        engine
+            .handlebars_mut()
+            .register_template_string("layout", layout)
+            .unwrap();
+        if let Some(partial) = partial {
+            engine
+                .handlebars_mut()
+                .register_template_string("content", partial)
+                .unwrap();
+        }
+
+        let values = json!({"content_template": "content", "name": "loki"});
+        engine.render("layout", None, values.as_object().unwrap())
+    }
+
+    #[test]
+    fn render_errors() {
+        assert_eq!(
+            "<p>loki</p>",
+            render(
+                "<p>{{> (lookup this \"content_template\")}}</p>",
+                Some("{{name}}")
+            )
+            .unwrap()
+        );
+
+        match render("<p>\n{{> (lookup this \"content_template\")}}</p>", None) {
+            Err(PageError::MissingPartial { template, partial }) => {
+                assert_eq!("layout", template);
+                assert_eq!("content", partial);
+            }
+            result => panic!("expected a missing partial got {result:?}"),
+        }
+
+        match render("<p>\n\n{{missing_helper name}}</p>", None) {
+            Err(PageError::Render { template, line, .. }) => {
+                assert_eq!("layout", template);
+                assert_eq!(Some(3), line);
+            }
+            result => panic!("expected a render error got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn translate() {
+        let mut engine = HandlebarsEngine::new();
+        engine.register_messages(Arc::new(Messages::new(
+            "en",
+            [
+                (
+                    String::from("en"),
+                    json!({"nav": {"donate": "Donate & more"}}),
+                ),
+                (String::from("es"), json!({"nav": {"donate": "Donar"}})),
+            ]
+            .into(),
+        )));
+        engine
+            .handlebars_mut()
+            .register_template_string(
+                "layout",
+                concat!(
+                    "{{t \"nav.donate\"}}|{{t \"nav.donate\" locale=\"en\"}}|",
+                    "{{localized_url \"/donate\"}}|{{localized_url \"/\" locale=\"en\"}}"
+                ),
+            )
+            .unwrap();
+
+        let values = json!({"locale": "es"});
+        assert_eq!(
+            "Donar|Donate &amp; more|/es/donate|/",
+            engine
+                .render("layout", None, values.as_object().unwrap())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn standard_helpers() {
+        let mut engine = HandlebarsEngine::new();
+        engine
+            .handlebars_mut()
+            .register_template_string(
+                "layout",
+                concat!(
+                    "{{{markdown notes}}}|",
+                    "{{format_date published format=\"%F %R\" timezone=\"America/Boise\"}}|",
+                    "{{relative_time published}}|",
+                    "{{count}} {{pluralize count \"person\" \"people\"}}|",
+                    "{{truncate notes 3}}|",
+                    "{{{url \"/news\" id page=2}}}|",
+                    "{{asset \"css/site.css\"}}",
+                ),
+            )
+            .unwrap();
+
+        let values = json!({
+            "notes": "*hi*",
+            "published": "2020-06-01T03:30:00Z",
+            "count": 2,
+            "id": 3,
+        });
+        let rendered = engine
+            .render("layout", None, values.as_object().unwrap())
+            .unwrap();
+        let parts = rendered.split('|').collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "<p><em>hi</em></p>",
+                "2020-05-31 21:30",
+                parts[2],
+                "2 people",
+                "*hi…",
+                "/news/3?page=2",
+                "/assets/css/site.css",
+            ],
+            parts
+        );
+        assert!(parts[2].ends_with("years ago"), "{}", parts[2]);
+
+        engine
+            .handlebars_mut()
+            .register_template_string("broken", "{{format_date notes}}")
+            .unwrap();
+        match engine.render("broken", None, values.as_object().unwrap()) {
+            Err(PageError::Render { message, .. }) => {
+                assert!(message.contains("format_date"), "{message}")
+            }
+            result => panic!("expected a render error got {result:?}"),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc, format::StrftimeItems};
+use chrono_tz::Tz;
+use serde_json::Value;
+
+// The helpers every engine registers, kept apart from the engines so they behave the
+// same whichever engine renders the template. Each engine adapts the arguments from
+// its own syntax, failures are returned as messages the engine reports as render
+// errors.
+
+/// The format of dates when the template doesn't give one, `June 1, 2025`
+pub(crate) const DATE_FORMAT: &str = "%B %-d, %Y";
+
+/// Where the assets are served from
+pub(crate) const ASSET_PATH: &str = "/assets";
+
+/// Renders github flavoured markdown, html within the text is escaped
+pub(crate) fn markdown(text: &str) -> Result<String, String> {
+    markdown::to_html_with_options(text, &markdown::Options::gfm())
+        .map_err(|e| format!("invalid markdown: {e}"))
+}
+
+/// Dates are accepted as RFC 3339 timestamps, timestamps without an offset or plain
+/// dates which are taken as UTC, or the seconds since the unix epoch
+pub(crate) fn parse_date(value: &Value) -> Result<DateTime<Utc>, String> {
+    match value {
+        Value::String(text) => DateTime::parse_from_rfc3339(text)
+            .map(|date| date.to_utc())
+            .or_else(|_| text.parse::<NaiveDateTime>().map(|date| date.and_utc()))
+            .or_else(|_| {
+                text.parse::<NaiveDate>()
+                    .map(|date| date.and_time(Default::default()).and_utc())
+            })
+            .map_err(|_| format!("'{text}' is not a date")),
+        Value::Number(seconds) => seconds
+            .as_i64()
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+            .ok_or_else(|| format!("{seconds} is not a timestamp")),
+        value => Err(format!("{value} is not a date")),
+    }
+}
+
+/// Formats the date with the `strftime` format in the time zone, UTC without one
+pub(crate) fn format_date(
+    value: &Value,
+    format: Option<&str>,
+    timezone: Option<&str>,
+) -> Result<String, String> {
+    let date = parse_date(value)?;
+    let format = format.unwrap_or(DATE_FORMAT);
+    let items = StrftimeItems::new(format)
+        .parse()
+        .map_err(|_| format!("'{format}' is not a valid date format"))?;
+
+    Ok(match timezone {
+        Some(timezone) => {
+            let timezone = timezone
+                .parse::<Tz>()
+                .map_err(|_| format!("'{timezone}' is not a time zone"))?;
+            date.with_timezone(&timezone)
+                .format_with_items(items.iter())
+                .to_string()
+        }
+        None => date.format_with_items(items.iter()).to_string(),
+    })
+}
+
+/// How long ago or until the date from now in the largest whole unit, `3 hours ago`
+/// or `in 2 days`, anything within a minute is `just now`
+pub(crate) fn relative_time(value: &Value, now: DateTime<Utc>) -> Result<String, String> {
+    const UNITS: [(&str, i64); 6] = [
+        ("year", 365 * 86_400),
+        ("month", 30 * 86_400),
+        ("week", 7 * 86_400),
+        ("day", 86_400),
+        ("hour", 3_600),
+        ("minute", 60),
+    ];
+
+    let delta: TimeDelta = parse_date(value)? - now;
+    let seconds = delta.num_seconds();
+    let Some((unit, count)) = UNITS
+        .iter()
+        .map(|(unit, length)| (*unit, seconds.abs() / length))
+        .find(|(_, count)| *count > 0)
+    else {
+        return Ok(String::from("just now"));
+    };
+
+    let unit = pluralize(count as f64, unit, None);
+    Ok(match seconds < 0 {
+        true => format!("{count} {unit} ago"),
+        false => format!("in {count} {unit}"),
+    })
+}
+
+/// The singular word for a count of one otherwise the plural, which is the singular
+/// with an `s` when not given
+pub(crate) fn pluralize(count: f64, singular: &str, plural: Option<&str>) -> String {
+    match (count == 1.0, plural) {
+        (true, _) => singular.to_owned(),
+        (false, Some(plural)) => plural.to_owned(),
+        (false, None) => format!("{singular}s"),
+    }
+}
+
+/// Cuts the text to the number of characters ending it with `end`, `…` by default
+pub(crate) fn truncate(text: &str, length: usize, end: Option<&str>) -> String {
+    match text.char_indices().nth(length) {
+        Some((index, _)) => format!("{}{}", text[..index].trim_end(), end.unwrap_or("…")),
+        None => text.to_owned(),
+    }
+}
+
+/// Builds a url from the path, the segments appended to it and the query, every
+/// part is percent encoded. The query is sorted by name, null values are left out and
+/// arrays repeat the name.
+pub(crate) fn url<'a, S, Q>(path: &str, segments: S, query: Q) -> String
+where
+    S: IntoIterator<Item = &'a Value>,
+    Q: IntoIterator<Item = (&'a str, &'a Value)>,
+{
+    let mut url = encode(path, b"/");
+    for segment in segments {
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        url.push_str(&encode(&value_text(segment), b""));
+    }
+
+    let mut query = query
+        .into_iter()
+        .flat_map(|(name, value)| match value {
+            Value::Array(values) => values.iter().map(|value| (name, value)).collect(),
+            value => vec![(name, value)],
+        })
+        .filter(|(_, value)| !value.is_null())
+        .map(|(name, value)| format!("{}={}", encode(name, b""), encode(&value_text(value), b"")))
+        .collect::<Vec<_>>();
+    query.sort();
+
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(&query.join("&"));
+    }
+    url
+}
+
+/// The url of a file within the assets
+pub(crate) fn asset_url(path: &str) -> String {
+    format!(
+        "{ASSET_PATH}/{}",
+        encode(path.trim_start_matches('/'), b"/")
+    )
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Percent encodes everything except the unreserved characters and those kept
+pub(crate) fn encode(text: &str, keep: &[u8]) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            byte if keep.contains(&byte) => char::from(byte).to_string(),
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn render_markdown() {
+        assert_eq!(
+            Ok(String::from("<p><em>hi</em> &lt;b&gt;</p>")),
+            markdown("*hi* <b>")
+        );
+    }
+
+    #[test]
+    fn format_dates() {
+        let date = json!("2025-06-01T03:30:00Z");
+        assert_eq!(
+            Ok(String::from("June 1, 2025")),
+            format_date(&date, None, None)
+        );
+        assert_eq!(
+            Ok(String::from("May 31, 2025 21:30 MDT")),
+            format_date(&date, Some("%B %-d, %Y %H:%M %Z"), Some("America/Boise"))
+        );
+        assert_eq!(
+            Ok(String::from("2025-06-01")),
+            format_date(&json!("2025-06-01"), Some("%F"), None)
+        );
+        assert_eq!(
+            Ok(String::from("2025-06-01 10:00")),
+            format_date(&json!("2025-06-01T10:00:00"), Some("%F %R"), None)
+        );
+        assert_eq!(
+            Ok(String::from("1970-01-02")),
+            format_date(&json!(86_400), Some("%F"), None)
+        );
+
+        assert!(format_date(&json!("yesterday"), None, None).is_err());
+        assert!(format_date(&json!(true), None, None).is_err());
+        assert!(format_date(&date, Some("%Q"), None).is_err());
+        assert!(format_date(&date, None, Some("Mars/Olympus")).is_err());
+    }
+
+    #[test]
+    fn relative_times() {
+        let now = parse_date(&json!("2025-06-01T12:00:00Z")).unwrap();
+        for (date, expected) in [
+            ("2025-06-01T11:59:30Z", "just now"),
+            ("2025-06-01T11:59:00Z", "1 minute ago"),
+            ("2025-06-01T09:00:00Z", "3 hours ago"),
+            ("2025-06-03T12:00:00Z", "in 2 days"),
+            ("2025-05-18T12:00:00Z", "2 weeks ago"),
+            ("2025-09-01T12:00:00Z", "in 3 months"),
+            ("2023-01-01T12:00:00Z", "2 years ago"),
+        ] {
+            assert_eq!(Ok(String::from(expected)), relative_time(&json!(date), now));
+        }
+    }
+
+    #[test]
+    fn pluralize_words() {
+        assert_eq!("article", pluralize(1.0, "article", None));
+        assert_eq!("articles", pluralize(0.0, "article", None));
+        assert_eq!("people", pluralize(2.0, "person", Some("people")));
+    }
+
+    #[test]
+    fn truncate_text() {
+        assert_eq!("short", truncate("short", 5, None));
+        assert_eq!("a long…", truncate("a long sentence", 7, None));
+        assert_eq!("héllo...", truncate("héllo wörld", 5, Some("...")));
+    }
+
+    #[test]
+    fn build_urls() {
+        assert_eq!("/news", url("/news", [], []));
+        assert_eq!(
+            "/news/3/caf%C3%A9%20menu",
+            url("/news", &[json!(3), json!("café menu")], [])
+        );
+        let query = [
+            ("q", &json!("rock & roll")),
+            ("page", &json!(2)),
+            ("tag", &json!(["a", "b"])),
+            ("empty", &Value::Null),
+        ];
+        assert_eq!(
+            "/search?page=2&q=rock%20%26%20roll&tag=a&tag=b",
+            url("/search", [], query)
+        );
+    }
+
+    #[test]
+    fn asset_urls() {
+        assert_eq!("/assets/css/site.css", asset_url("css/site.css"));
+        assert_eq!(
+            "/assets/images/my%20logo.png",
+            asset_url("/images/my logo.png")
+        );
+    }
+}
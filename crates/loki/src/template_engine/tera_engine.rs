@@ -0,0 +1,563 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde_json::{Map, Value};
+use tera::{ErrorKind, Tera};
+
+use super::{TemplateEngine, TemplateFile, helpers};
+use crate::PageError;
+use crate::assets::AssetManifest;
+use crate::i18n::Messages;
+
+///
+/// Renders `.tera` templates, a page's template extends the layout itself so the
+/// template is rendered when there is one otherwise the layout is rendered. Templates
+/// reference each other by their path within the template directory
+/// (`{% extends "layout/main.tera" %}`) while pages use the registry name.
+///
+/// Fragments render a template's `content` block alone, the layouts templates extend
+/// are swapped for one which only has that block.
+///
+/// The standard helpers are registered as filters and functions, tera's own `truncate`
+/// and `date` filters are left as they are
+///
+/// ```jinja
+/// {{ notes | markdown }}
+/// {{ published | format_date(format="%d %b %Y", timezone="America/Boise") }}
+/// {{ published | relative_time }}
+/// {{ count }} {{ pluralize(count=count, singular="person", plural="people") }}
+/// <a href="{{ url(path="/news", segments=[id], page=2) }}">
+/// <link href="{{ asset(path="css/site.css") }}" rel="stylesheet">
+/// ```
+///
+pub struct TeraEngine {
+    tera: Tera,
+    /// The templates with the layouts they extend replaced, for rendering fragments
+    fragments: Tera,
+    /// The block of the layout rendered as the fragment
+    fragment_block: String,
+    /// The registry name of each template mapped to the name within tera
+    names: BTreeMap<String, String>,
+}
+
+impl Default for TeraEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TeraEngine {
+    /// Creates an engine which escapes the values rendered in `.tera` templates
+    pub fn new() -> Self {
+        let mut tera = Tera::default();
+        tera.autoescape_on(vec![".tera"]);
+        register_helpers(&mut tera);
+        Self {
+            fragments: tera.clone(),
+            tera,
+            fragment_block: String::from("content"),
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// The block rendered for fragments, `content` by default
+    pub fn fragment_block<B>(mut self, block: B) -> Self
+    where
+        B: Into<String>,
+    {
+        self.fragment_block = block.into();
+        self
+    }
+
+    pub fn register_filter<N, F>(mut self, name: N, filter: F) -> Self
+    where
+        N: AsRef<str>,
+        F: tera::Filter + 'static,
+    {
+        self.tera.register_filter(name.as_ref(), filter);
+        self
+    }
+
+    pub fn register_function<N, F>(mut self, name: N, function: F) -> Self
+    where
+        N: AsRef<str>,
+        F: tera::Function + 'static,
+    {
+        self.tera.register_function(name.as_ref(), function);
+        self
+    }
+
+    /// Access to the underlying tera instance for anything else
+    pub fn tera_mut(&mut self) -> &mut Tera {
+        &mut self.tera
+    }
+
+    fn tera_name(&self, name: &str) -> Option<&str> {
+        self.names.get(name).map(String::as_str)
+    }
+
+    /// Copies the templates replacing every layout which is extended with a template
+    /// that only renders the fragment block
+    fn build_fragments(&self) -> Result<Tera> {
+        let mut fragments = self.tera.clone();
+        let layouts = self
+            .tera
+            .templates
+            .values()
+            .filter_map(|template| template.parents.last())
+            .collect::<BTreeSet<_>>();
+
+        let block = &self.fragment_block;
+        fragments
+            .add_raw_templates(layouts.into_iter().map(|layout| {
+                (
+                    layout.as_str(),
+                    format!("{{% block {block} %}}{{% endblock {block} %}}"),
+                )
+            }))
+            .with_context(|| "failed to build the tera fragment templates")?;
+        Ok(fragments)
+    }
+
+    fn render_with(
+        &self,
+        tera: &Tera,
+        name: &str,
+        tera_name: &str,
+        values: &Map<String, Value>,
+    ) -> Result<String, PageError> {
+        let context =
+            tera::Context::from_serialize(values).map_err(|e| PageError::Serialization {
+                name: String::from("context"),
+                message: e.to_string(),
+            })?;
+        tera.render(tera_name, &context)
+            .map_err(|e| render_error(name, &e))
+    }
+}
+
+impl TemplateEngine for TeraEngine {
+    fn extension(&self) -> &'static str {
+        "tera"
+    }
+
+    fn register(&mut self, templates: &[TemplateFile]) -> Result<()> {
+        for template in templates {
+            tracing::info!(
+                path = ?template.path,
+                "registering template '{}' as '{}'",
+                template.name,
+                template.relative_path
+            );
+        }
+
+        self.tera
+            .add_template_files(
+                templates
+                    .iter()
+                    .map(|template| (&template.path, Some(template.relative_path.as_str()))),
+            )
+            .with_context(|| "failed to register tera templates")?;
+
+        self.names.extend(
+            templates
+                .iter()
+                .map(|template| (template.name.clone(), template.relative_path.clone())),
+        );
+        self.fragments = self.build_fragments()?;
+        Ok(())
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.names.contains_key(name)
+    }
+
+    fn register_messages(&mut self, messages: Arc<Messages>) {
+        // tera functions can't see the page's values so the locale is passed along
+        let translate = Translate(messages.clone());
+        self.tera.register_function("t", translate.clone());
+        self.fragments.register_function("t", translate);
+
+        // `{{ localized_url(path="/news", locale=locale) }}`
+        let localized_url = Safe(move |args: &HashMap<String, Value>| {
+            let path = str_arg(args, "path")
+                .ok_or_else(|| tera::Error::msg("`localized_url` requires the `path`"))?;
+            let path = messages.localized_path(str_arg(args, "locale"), path);
+            Ok(Value::String(helpers::url(&path, [], [])))
+        });
+        self.tera
+            .register_function("localized_url", localized_url.clone());
+        self.fragments
+            .register_function("localized_url", localized_url);
+    }
+
+    fn register_assets(&mut self, assets: Arc<AssetManifest>) {
+        let asset = Safe({
+            let assets = assets.clone();
+            move |args: &HashMap<String, Value>| {
+                let path = str_arg(args, "path")
+                    .ok_or_else(|| tera::Error::msg("`asset` requires the `path`"))?;
+                Ok(Value::String(assets.url(path)))
+            }
+        });
+        self.tera.register_function("asset", asset.clone());
+        self.fragments.register_function("asset", asset);
+
+        // `{% for url in bundle(name="css/main.css") %}<link href="{{ url }}">{% endfor %}`
+        let bundle = move |args: &HashMap<String, Value>| {
+            let name = str_arg(args, "name")
+                .ok_or_else(|| tera::Error::msg("`bundle` requires the `name`"))?;
+            assets
+                .bundle_urls(name)
+                .map(Value::from)
+                .ok_or_else(|| tera::Error::msg(format!("there is no bundle named '{name}'")))
+        };
+        self.tera.register_function("bundle", bundle.clone());
+        self.fragments.register_function("bundle", bundle);
+    }
+
+    fn render(
+        &self,
+        layout: &str,
+        template: Option<&str>,
+        values: &Map<String, Value>,
+    ) -> Result<String, PageError> {
+        let name = template.unwrap_or(layout);
+        let tera_name = self
+            .tera_name(name)
+            .ok_or_else(|| PageError::TemplateNotFound(layout.to_owned(), name.to_owned()))?;
+        self.render_with(&self.tera, name, tera_name, values)
+    }
+
+    fn render_fragment(
+        &self,
+        template: &str,
+        values: &Map<String, Value>,
+    ) -> Result<String, PageError> {
+        let tera_name = self
+            .tera_name(template)
+            .ok_or_else(|| PageError::TemplateNotFound(String::new(), template.to_owned()))?;
+
+        // a template which doesn't extend a layout is already a fragment
+        let extends = self
+            .tera
+            .templates
+            .get(tera_name)
+            .is_some_and(|template| !template.parents.is_empty());
+        let tera = if extends { &self.fragments } else { &self.tera };
+        self.render_with(tera, template, tera_name, values)
+    }
+}
+
+/// `{{ t(key="nav.donate", locale=locale) }}` looks up the message in the locale, the
+/// default locale's when no locale is given
+#[derive(Clone)]
+struct Translate(Arc<Messages>);
+
+impl tera::Function for Translate {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let key = args
+            .get("key")
+            .and_then(Value::as_str)
+            .ok_or_else(|| tera::Error::msg("`t` requires the message's `key`"))?;
+        let locale = args.get("locale").and_then(Value::as_str);
+        Ok(Value::String(self.0.translate(locale, key)))
+    }
+}
+
+fn register_helpers(tera: &mut Tera) {
+    tera.register_filter(
+        "markdown",
+        Safe(|value: &Value, _: &HashMap<String, Value>| {
+            let text = value
+                .as_str()
+                .ok_or_else(|| tera::Error::msg(format!("`markdown` requires text: {value}")))?;
+            helpers::markdown(text)
+                .map(Value::String)
+                .map_err(tera::Error::msg)
+        }),
+    );
+    tera.register_filter(
+        "format_date",
+        |value: &Value, args: &HashMap<String, Value>| {
+            helpers::format_date(value, str_arg(args, "format"), str_arg(args, "timezone"))
+                .map(Value::String)
+                .map_err(tera::Error::msg)
+        },
+    );
+    tera.register_filter(
+        "relative_time",
+        |value: &Value, _: &HashMap<String, Value>| {
+            helpers::relative_time(value, Utc::now())
+                .map(Value::String)
+                .map_err(tera::Error::msg)
+        },
+    );
+    tera.register_function("pluralize", |args: &HashMap<String, Value>| {
+        let count = args
+            .get("count")
+            .and_then(Value::as_f64)
+            .ok_or_else(|| tera::Error::msg("`pluralize` requires a numeric `count`"))?;
+        let singular = str_arg(args, "singular")
+            .ok_or_else(|| tera::Error::msg("`pluralize` requires the `singular` word"))?;
+        Ok(Value::String(helpers::pluralize(
+            count,
+            singular,
+            str_arg(args, "plural"),
+        )))
+    });
+    // the helpers encode everything but the url's separators so they're left unescaped
+    tera.register_function(
+        "url",
+        Safe(|args: &HashMap<String, Value>| {
+            let path = str_arg(args, "path")
+                .ok_or_else(|| tera::Error::msg("`url` requires the `path`"))?;
+            let segments = match args.get("segments") {
+                Some(Value::Array(segments)) => segments.as_slice(),
+                Some(segment) => std::slice::from_ref(segment),
+                None => &[],
+            };
+            let query = args
+                .iter()
+                .filter(|(name, _)| !matches!(name.as_str(), "path" | "segments"))
+                .map(|(name, value)| (name.as_str(), value));
+            Ok(Value::String(helpers::url(path, segments, query)))
+        }),
+    );
+    tera.register_function(
+        "asset",
+        Safe(|args: &HashMap<String, Value>| {
+            let path = str_arg(args, "path")
+                .ok_or_else(|| tera::Error::msg("`asset` requires the `path`"))?;
+            Ok(Value::String(helpers::asset_url(path)))
+        }),
+    );
+}
+
+fn str_arg<'a>(args: &'a HashMap<String, Value>, name: &str) -> Option<&'a str> {
+    args.get(name).and_then(Value::as_str)
+}
+
+/// Marks the output of a filter or function as html which isn't escaped
+#[derive(Clone)]
+struct Safe<F>(F);
+
+impl<F> tera::Filter for Safe<F>
+where
+    F: tera::Filter,
+{
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        self.0.filter(value, args)
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+impl<F> tera::Function for Safe<F>
+where
+    F: tera::Function,
+{
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        self.0.call(args)
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// Tera wraps the cause of a failure in a generic "Failed to render" error, the
+/// whole chain is reported with a missing include or macro file as a missing partial
+fn render_error(template: &str, error: &tera::Error) -> PageError {
+    let mut messages = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        if let Some(cause) = cause.downcast_ref::<tera::Error>()
+            && let ErrorKind::TemplateNotFound(partial) = &cause.kind
+        {
+            // includes report the list of templates they tried as `[name]`
+            return PageError::MissingPartial {
+                template: template.to_owned(),
+                partial: partial.trim_matches(['[', ']']).to_owned(),
+            };
+        }
+        messages.push(cause.to_string());
+        source = cause.source();
+    }
+
+    if let ErrorKind::TemplateNotFound(partial) = &error.kind {
+        return PageError::MissingPartial {
+            template: template.to_owned(),
+            partial: partial.clone(),
+        };
+    }
+
+    PageError::Render {
+        template: template.to_owned(),
+        line: None,
+        message: messages.join(": "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn template(directory: &std::path::Path, relative_path: &str, text: &str) -> TemplateFile {
+        let path = directory.join(relative_path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, text).unwrap();
+        TemplateFile {
+            name: format!("templates/{}", relative_path.trim_end_matches(".tera")),
+            relative_path: relative_path.to_string(),
+            path,
+        }
+    }
+
+    #[test]
+    fn render_with_layout() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let templates = [
+            template(
+                directory,
+                "layout/main.tera",
+                "<title>{{ title }}</title>{% block content %}{% endblock content %}",
+            ),
+            template(
+                directory,
+                "content/news.tera",
+                "{% extends \"layout/main.tera\" %}{% block content %}{{ news }}{% endblock content %}",
+            ),
+        ];
+
+        let mut engine = TeraEngine::new();
+        engine.register(&templates).unwrap();
+        assert!(engine.has_template("templates/content/news"));
+        assert!(!engine.has_template("content/news.tera"));
+
+        let values = json!({"title": "News", "news": "<b>today</b>"});
+        let html = engine
+            .render(
+                "templates/layout/main",
+                Some("templates/content/news"),
+                values.as_object().unwrap(),
+            )
+            .unwrap();
+        assert_eq!("<title>News</title>&lt;b&gt;today&lt;&#x2F;b&gt;", html);
+
+        let fragment = engine
+            .render_fragment("templates/content/news", values.as_object().unwrap())
+            .unwrap();
+        assert_eq!("&lt;b&gt;today&lt;&#x2F;b&gt;", fragment);
+        let layout = engine
+            .render_fragment("templates/layout/main", values.as_object().unwrap())
+            .unwrap();
+        assert_eq!("<title>News</title>", layout);
+    }
+
+    #[test]
+    fn render_errors() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let templates = [
+            template(
+                directory,
+                "content/include.tera",
+                "{% include \"content/missing.tera\" %}",
+            ),
+            template(directory, "content/filter.tera", "{{ name | upper }}"),
+        ];
+
+        let mut engine = TeraEngine::new();
+        engine.register(&templates).unwrap();
+        let values = json!({"name": 7});
+        let values = values.as_object().unwrap();
+
+        match engine.render("templates/content/include", None, values) {
+            Err(PageError::MissingPartial { template, partial }) => {
+                assert_eq!("templates/content/include", template);
+                assert_eq!("content/missing.tera", partial);
+            }
+            result => panic!("expected a missing partial got {result:?}"),
+        }
+
+        match engine.render("templates/content/filter", None, values) {
+            Err(PageError::Render {
+                template, message, ..
+            }) => {
+                assert_eq!("templates/content/filter", template);
+                assert!(message.contains("upper"), "{message}");
+            }
+            result => panic!("expected a render error got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn render_missing_parent() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let templates = [template(
+            directory,
+            "content/news.tera",
+            "{% extends \"layout/other.tera\" %}",
+        )];
+
+        assert!(TeraEngine::new().register(&templates).is_err());
+    }
+
+    #[test]
+    fn standard_helpers() {
+        let mut engine = TeraEngine::new();
+        engine
+            .tera_mut()
+            .add_raw_template(
+                "helpers.tera",
+                concat!(
+                    "{{ notes | markdown }}|",
+                    "{{ published | format_date(format=\"%F %R\", timezone=\"America/Boise\") }}|",
+                    "{{ published | relative_time }}|",
+                    "{{ count }} {{ pluralize(count=count, singular=\"person\", plural=\"people\") }}|",
+                    "{{ notes | truncate(length=3) }}|",
+                    "{{ url(path=\"/news\", segments=[id], page=2, q=\"a b\") }}|",
+                    "{{ asset(path=\"css/site.css\") }}",
+                ),
+            )
+            .unwrap();
+        engine.names.insert(
+            String::from("templates/helpers"),
+            String::from("helpers.tera"),
+        );
+
+        let values = json!({
+            "notes": "*hi*",
+            "published": "2020-06-01T03:30:00Z",
+            "count": 1,
+            "id": 3,
+        });
+        let rendered = engine
+            .render("templates/helpers", None, values.as_object().unwrap())
+            .unwrap();
+        let parts = rendered.split('|').collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "<p><em>hi</em></p>",
+                "2020-05-31 21:30",
+                parts[2],
+                "1 person",
+                "*hi…",
+                "/news/3?page=2&q=a%20b",
+                "/assets/css/site.css",
+            ],
+            parts
+        );
+        assert!(parts[2].ends_with("years ago"), "{}", parts[2]);
+    }
+}
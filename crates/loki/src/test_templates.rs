@@ -0,0 +1,53 @@
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+use crate::registry::Registry;
+use crate::reload::Reloadable;
+use crate::template_engine::TemplateEngine;
+
+/// The handlebars layout most tests render their content templates in
+pub(crate) const LAYOUT: (&str, &str) = (
+    "layout/main.hbs",
+    "<main>{{> (lookup this \"content_template\")}}</main>",
+);
+
+///
+/// A `templates` directory written to a temporary directory for a test, the files are
+/// removed when this is dropped so keep it alive for as long as the templates are read.
+///
+pub(crate) struct TestTemplates {
+    directory: TempDir,
+}
+
+impl TestTemplates {
+    /// Writes each file, given by its path relative to the `templates` directory
+    pub(crate) fn new(files: &[(&str, &str)]) -> Self {
+        let directory = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = directory.path().join("templates").join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        Self { directory }
+    }
+
+    /// The temporary directory holding `templates`, for anything else a test writes
+    pub(crate) fn directory(&self) -> &Path {
+        self.directory.path()
+    }
+
+    pub(crate) fn templates(&self) -> PathBuf {
+        self.directory.path().join("templates")
+    }
+
+    pub(crate) fn registry(&self, engines: Vec<Box<dyn TemplateEngine>>) -> Registry {
+        Registry::load(&[self.templates().to_string_lossy()], engines).unwrap()
+    }
+
+    /// A handlebars only registry which reloads from the templates directory
+    pub(crate) fn reloadable(&self) -> Reloadable<Registry> {
+        let templates = self.templates().to_string_lossy().to_string();
+        Reloadable::new(move || Registry::load(&[&templates], vec![])).unwrap()
+    }
+}
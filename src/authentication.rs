@@ -1,14 +1,14 @@
 use std::{
+    future::{Ready, ready},
     sync::{Arc, OnceLock},
     time::Duration,
 };
 
 use anyhow::Context;
 use axum::{
-    Form, Router,
-    body::Body,
+    Extension, Form, Router,
     extract::{Request, State},
-    http::{StatusCode, header},
+    http::{header, request::Parts},
     middleware::Next,
     response::{IntoResponse, Redirect, Response},
     routing::{get, post},
@@ -16,9 +16,11 @@ use axum::{
 use axum_extra::extract::{CookieJar, cookie::Cookie};
 use deadpool_postgres::Pool;
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use loki::PageBuilder;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error};
 
+use crate::routes::MAIN_LAYOUT;
 use crate::services::{User, UserStore};
 
 const TOKEN_COOKIE: &str = "phrt-token";
@@ -47,10 +49,7 @@ where
 fn token_secret() -> &'static [u8] {
     static ENV_VAL: OnceLock<Option<String>> = OnceLock::new();
 
-    match ENV_VAL.get_or_init(|| match std::env::var("PHRT_TOKEN_SECRET") {
-        Ok(value) => Some(value),
-        _ => None,
-    }) {
+    match ENV_VAL.get_or_init(|| std::env::var("PHRT_TOKEN_SECRET").ok()) {
         Some(secret) => secret.as_bytes(),
         _ => ENCODING_KEY.as_bytes(),
     }
@@ -81,75 +80,17 @@ impl TryFrom<UserClaims> for User {
     type Error = serde_json::Error;
 
     fn try_from(value: UserClaims) -> std::result::Result<Self, Self::Error> {
-        Ok(serde_json::from_str(&value.user)?)
-    }
-}
-
-#[derive(bon::Builder)]
-#[builder(finish_fn = build, on(String, into))]
-pub struct TeraTemplate {
-    #[builder(field)]
-    context: tera::Context,
-    #[builder(with = |tera: &tera::Tera| tera.clone())]
-    tera: tera::Tera,
-    template: String,
-}
-
-impl IntoResponse for TeraTemplate {
-    fn into_response(self) -> Response {
-        match self.tera.render(self.template.as_ref(), &self.context) {
-            Ok(html) => {
-                tracing::debug!(
-                    context = ?self.context,
-                    "successfully rendered template '{}'",
-                    self.template
-                );
-
-                Response::builder()
-                    .status(StatusCode::OK)
-                    .header(header::CONTENT_TYPE, "text/html")
-                    .body(Body::from(html))
-                    .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
-            }
-            Err(e) => {
-                tracing::error!(
-                    context = ?self.context,
-                    error = ?e,
-                    "failed to render template '{}'",
-                    self.template
-                );
-                #[cfg(debug_assertions)]
-                return axum_extra::response::InternalServerError(e).into_response();
-                #[cfg(not(debug_assertions))]
-                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-            }
-        }
-    }
-}
-
-impl<S> TeraTemplateBuilder<S>
-where
-    S: tera_template_builder::State,
-{
-    pub fn value<N, V>(mut self, name: N, value: &V) -> Self
-    where
-        N: AsRef<str>,
-        V: Serialize,
-    {
-        self.context.insert(name.as_ref(), value);
-        self
+        serde_json::from_str(&value.user)
     }
 }
 
 struct LoginRouteState {
     user_store: UserStore,
-    tera: Arc<tera::Tera>,
 }
 
-pub fn login_routes(database_pool: Pool, tera: Arc<tera::Tera>) -> Router {
+pub fn login_routes(database_pool: Pool) -> Router {
     let state = Arc::new(LoginRouteState {
         user_store: UserStore::builder().database_pool(database_pool).build(),
-        tera,
     });
 
     Router::new()
@@ -159,15 +100,15 @@ pub fn login_routes(database_pool: Pool, tera: Arc<tera::Tera>) -> Router {
 }
 
 async fn login_get(
-    State(state): State<Arc<LoginRouteState>>,
+    Extension(page): Extension<PageBuilder>,
     cookies: CookieJar,
 ) -> impl IntoResponse {
     (
         cookies.remove(TOKEN_COOKIE).remove(REFRESH_COOKIE),
-        TeraTemplate::builder()
-            .tera(&state.tera)
-            .template("content/login.tera")
-            .build(),
+        page.html()
+            .layout(MAIN_LAYOUT)
+            .template("templates/content/login")
+            .send(),
     )
         .into_response()
 }
@@ -199,12 +140,14 @@ where
 #[axum::debug_handler]
 async fn login_post(
     State(state): State<Arc<LoginRouteState>>,
+    Extension(page): Extension<PageBuilder>,
     cookies: CookieJar,
     Form(login_form): Form<LoginInfo>,
 ) -> Response {
-    let template = TeraTemplate::builder()
-        .tera(&state.tera)
-        .template("content/login.tera")
+    let template = page
+        .html()
+        .layout(MAIN_LAYOUT)
+        .template("templates/content/login")
         .value("email", &login_form.email);
 
     let LoginInfo {
@@ -215,18 +158,18 @@ async fn login_post(
     else {
         tracing::debug!("no login information was provided");
         // todo send template?
-        return template.value("login_error", &true).build().into_response();
+        return template.value("login_error", true).send().into_response();
     };
 
     let user = match state.user_store.for_email(user_email.clone()).await {
         Ok(Some(user)) => user,
         Ok(None) => {
             tracing::debug!("failed to locate user '{user_email}'");
-            return template.value("login_error", &true).build().into_response();
+            return template.value("login_error", true).send().into_response();
         }
         Err(e) => {
             tracing::error!("failed to retrieve the user from teh database: {e}");
-            return template.value("login_error", &true).build().into_response();
+            return template.value("login_error", true).send().into_response();
         }
     };
 
@@ -235,7 +178,7 @@ async fn login_post(
             user = ?user,
             "{user} failed to authenticate"
         );
-        return template.value("login_error", &true).build().into_response();
+        return template.value("login_error", true).send().into_response();
     }
 
     let refresh_token = match state.user_store.refresh_token(&user).await {
@@ -247,7 +190,7 @@ async fn login_post(
                 "failed to retrieve {user} refresh token: {e}"
             );
 
-            return template.value("login_error", &true).build().into_response();
+            return template.value("login_error", true).send().into_response();
         }
     };
 
@@ -281,7 +224,7 @@ async fn login_post(
 }
 
 fn get_cookies_from_request(
-    request: &Request,
+    _request: &Request,
     cookies: &CookieJar,
 ) -> (Option<String>, Option<String>) {
     (
@@ -294,6 +237,38 @@ fn get_cookies_from_request(
     )
 }
 
+/// Authorizes the admin endpoints for requests carrying the bearer token, without a
+/// token every request is refused
+pub fn authorize_admin(token: Option<String>) -> impl Fn(&Parts) -> Ready<bool> + Send + Sync {
+    if token.is_none() {
+        tracing::warn!("no admin token is configured, the admin endpoints refuse every request");
+    }
+
+    move |parts: &Parts| {
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        ready(match (&token, bearer) {
+            (Some(token), Some(bearer)) => constant_time_eq(token.as_bytes(), bearer.as_bytes()),
+            _ => false,
+        })
+    }
+}
+
+/// Compares the tokens without returning early so the time taken doesn't reveal how
+/// much of the token matched
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .fold(0, |difference, (left, right)| difference | (left ^ right))
+            == 0
+}
+
 /// This is a middleware function that will place the user into the request if the cookies exist and are valid
 pub async fn authenticate(
     State(user_store): State<Arc<UserStore>>,
@@ -309,7 +284,7 @@ pub async fn authenticate(
                 debug!("{user} was authenticated via jwk");
                 (Some(user), CookieJar::clone(&cookies))
             }
-            Err(e) => {
+            Err(_e) => {
                 tracing::error!("failed to retrieve user form the claims");
                 (
                     None,
@@ -330,7 +305,7 @@ pub async fn authenticate(
                         Duration::from_secs(TOKEN_DURATION_SECS),
                     )),
                 ),
-                Err(e) => {
+                Err(_e) => {
                     error!("failed to create jwk for {user}");
                     (None, CookieJar::clone(&cookies).remove(REFRESH_COOKIE))
                 }
@@ -352,3 +327,28 @@ pub async fn authenticate(
     let response = next.run(request).await;
     (new_cookies, response).into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    async fn authorized(token: Option<&str>, authorization: Option<&str>) -> bool {
+        let mut request = Request::builder();
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let (parts, _) = request.body(()).unwrap().into_parts();
+        authorize_admin(token.map(String::from))(&parts).await
+    }
+
+    #[tokio::test]
+    async fn authorize_admin_token() {
+        assert!(authorized(Some("secret"), Some("Bearer secret")).await);
+        assert!(!authorized(Some("secret"), Some("Bearer other")).await);
+        assert!(!authorized(Some("secret"), Some("secret")).await);
+        assert!(!authorized(Some("secret"), None).await);
+        assert!(!authorized(None, Some("Bearer secret")).await);
+    }
+}
@@ -14,6 +14,36 @@ pub struct Config {
     pub templates: String,
     #[arg(long, env = "ASSET_DIR", default_value_t = String::from("./assets"))]
     pub asset_dir: String,
+    /// The url prefix the assets are served at
+    #[arg(long, env = "ASSET_MOUNT", default_value_t = String::from("/assets"))]
+    pub asset_mount: String,
+    /// The directory of the message catalogs, a `{locale}.json` for each locale
+    #[arg(long, env = "LOCALES", default_value_t = String::from("./locales"))]
+    pub locales: String,
+
+    /// The bearer token which authorizes the cache admin endpoints at `/admin/caches`,
+    /// every request to them is refused without one
+    #[arg(long, env = "ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// The url the site is served from, the sitemap uses the request's host without it
+    #[arg(long, env = "SITE_URL")]
+    pub site_url: Option<String>,
+
+    /// Reloads the templates in place when they change rather than requiring a restart,
+    /// this is ignored by release builds and builds without the `hot-reload` feature
+    #[arg(long, env = "HOT_RELOAD", default_value_t = false)]
+    pub hot_reload: bool,
+
+    /// Writes a compressed copy of the assets alongside them when starting, which are
+    /// served rather than compressing the assets for each request
+    #[arg(long, env = "PRECOMPRESS_ASSETS", default_value_t = false)]
+    pub precompress_assets: bool,
+
+    /// Writes the site as static files to the directory and exits rather than serving it,
+    /// the export can be served by any static host while the server is down
+    #[arg(long, env = "EXPORT_DIR")]
+    pub export: Option<String>,
 
     /// Causes the application to invoke a full reset on the datbase, revert everything
     /// then reapply the migrations, this *CAN* cause data loss
@@ -4,30 +4,55 @@ use crate::config::Config;
 use crate::migrations::DATABASE_REVISIONS;
 use anyhow::{Context, Result};
 use deadpool_postgres::{Manager, Pool};
-use loki_migration::MigrationBuilder;
+use loki::InvalidationBus;
+use loki_migration::migrate_store::RevisionDatabase;
+use loki_migration::postgres_revision_storage::PostgresRevisionStorage;
+use loki_migration::{Migration, MigrationBuilder};
 
 use tracing::instrument;
 
 #[instrument(level = "info", skip_all)]
 pub async fn initialize_database(config: &Config) -> Result<Pool> {
-    let builder = openssl::ssl::SslConnector::builder(openssl::ssl::SslMethod::tls())
-        .expect("failed to make connect");
-    let tls = postgres_openssl::MakeTlsConnector::new(builder.build());
     let database_config = tokio_postgres::Config::from_str(&config.database_url)?;
-    let pool = Pool::builder(Manager::new(database_config, tls))
+    let pool = Pool::builder(Manager::new(database_config, tls_connector()))
         .build()
         .with_context(|| "failed to create the database pool")?;
-    exeute_migrations(&config, &pool).await?;
+    exeute_migrations(config, &pool).await?;
 
     tracing::debug!("connected to database");
     Ok(pool)
 }
 
-async fn exeute_migrations(config: &Config, database_pool: &Pool) -> Result<()> {
-    let migration = MigrationBuilder::postgres()
+/// Creates the bus which clears the caches of every instance when one of them changes
+/// the underlying data, it listens on its own connection outside of the pool and
+/// notifies the other instances from the pool
+pub fn create_invalidation_bus(config: &Config, database_pool: &Pool) -> Result<InvalidationBus> {
+    let database_config = tokio_postgres::Config::from_str(&config.database_url)?;
+    Ok(InvalidationBus::builder()
+        .config(database_config)
+        .tls(tls_connector())
+        .pool(database_pool.clone())
+        .build())
+}
+
+fn tls_connector() -> postgres_openssl::MakeTlsConnector {
+    let builder = openssl::ssl::SslConnector::builder(openssl::ssl::SslMethod::tls())
+        .expect("failed to make connect");
+    postgres_openssl::MakeTlsConnector::new(builder.build())
+}
+
+pub type DatabaseMigration = Migration<RevisionDatabase<PostgresRevisionStorage>>;
+
+/// Creates the migration for the revisions of this build against the pool
+pub fn create_migration(database_pool: &Pool) -> DatabaseMigration {
+    MigrationBuilder::postgres()
         .database_pool(database_pool)
         .revisions(DATABASE_REVISIONS)
-        .build();
+        .build()
+}
+
+async fn exeute_migrations(config: &Config, database_pool: &Pool) -> Result<()> {
+    let migration = create_migration(database_pool);
 
     if config.reset_datbase {
         migration
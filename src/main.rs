@@ -5,11 +5,22 @@ use std::sync::Arc;
 use anyhow::Context;
 use axum::Extension;
 use clap::Parser;
-use tera::Tera;
+use loki::{
+    CacheRegistry,
+    assets::{AssetBundle, AssetDirectory},
+    i18n::Locales,
+    template_engine::TeraEngine,
+};
 use tracing_subscriber::EnvFilter;
 
-use crate::{config::Config, database::initialize_database};
+use crate::{
+    authentication::authorize_admin,
+    config::Config,
+    database::{create_invalidation_bus, create_migration, initialize_database},
+};
 
+// the login flow isn't merged into the routes yet, see `routes::create_routes`
+#[allow(dead_code)]
 mod authentication;
 mod config;
 mod database;
@@ -41,27 +52,52 @@ async fn main() -> anyhow::Result<()> {
         .await
         .with_context(|| "failed to initialize the database")?;
 
-    let mut tera = Tera::new(&format!("{}/**/*.tera", args.templates))
-        .inspect_err(|e| {
-            tracing::error!(
-                directory = args.templates,
-                "failed to register templates: {e}"
-            );
-        })
-        .with_context(|| "failed to register templates")?;
+    let invalidation = Arc::new(
+        create_invalidation_bus(&args, &database_pool)
+            .with_context(|| "failed to create the cache invalidation bus")?,
+    );
 
-    tera.autoescape_on(vec![".tera"]);
-    tera.register_filter("markdown", routes::MarkdownFilter);
+    let caches = Arc::new(CacheRegistry::with_invalidation(invalidation.clone()));
+    routes::register_caches(&caches);
 
-    let app = routes::create_routes(&args, &database_pool, Arc::new(tera.clone()))
-        .layer(Extension(Arc::new(tera)))
+    let app = routes::create_routes(&args, &database_pool, &invalidation)
         .layer(Extension(database_pool.clone()));
 
+    invalidation.listen();
+    let migration = Arc::new(create_migration(&database_pool));
+
     loki::Application::run()
         .port(args.port)
         .extension(database_pool.clone())
-        .assets(&args.asset_dir)
+        .migrations(move || {
+            let migration = migration.clone();
+            async move { migration.status().await }
+        })
+        .caches(caches, authorize_admin(args.admin_token.clone()))
+        .hot_reload(args.hot_reload)
+        .maybe_site_url(args.site_url.clone())
+        .maybe_export(args.export.clone())
+        .clear_on_reload(&routes::CONTENT_RESPONSES)
+        .clear_on_reload(&routes::NEWS_RESPONSES)
+        .assets(
+            AssetDirectory::builder()
+                .path(&args.asset_dir)
+                .mount(&args.asset_mount)
+                .build(),
+        )
+        .precompress_assets(args.precompress_assets)
+        .bundle(AssetBundle::new(
+            "css/main.css",
+            ["css/theme.css", "css/site.css", "css/icons.css"],
+        ))
         .templates(&args.templates)
+        .locales(
+            Locales::builder()
+                .supported(vec![String::from("es")])
+                .catalogs(&args.locales)
+                .build(),
+        )
+        .engine(|| Box::new(TeraEngine::new()))
         .routes(app)
         .finish()
         .await?;
@@ -1,47 +1,53 @@
-use std::sync::Arc;
+use std::time::Duration;
 
-use crate::authentication::TeraTemplate;
-use axum::{Extension, response::IntoResponse};
-use tera::Tera;
+use axum::Extension;
+use loki::{PageBuilder, PageResult, ResponseCache};
 
-pub async fn home(Extension(tera): Extension<Arc<Tera>>) -> impl IntoResponse {
-    TeraTemplate::builder()
-        .tera(&tera)
-        .template("content/home.tera")
-        .build()
+use super::{MAIN_LAYOUT, MARKDOWN_LAYOUT};
+
+/// The content pages only change with a deploy, so browsers can reuse them briefly
+pub static CONTENT_RESPONSES: ResponseCache =
+    ResponseCache::const_ttl(32, Duration::from_secs(60 * 60))
+        .with_max_age(Duration::from_secs(5 * 60));
+
+pub async fn home(Extension(page): Extension<PageBuilder>) -> PageResult {
+    page.html()
+        .layout(MAIN_LAYOUT)
+        .template("templates/content/home")
+        .send()
 }
 
-pub async fn volunteer(Extension(tera): Extension<Arc<Tera>>) -> impl IntoResponse {
-    TeraTemplate::builder()
-        .tera(&tera)
-        .template("content/volunteer.tera")
-        .build()
+pub async fn volunteer(Extension(page): Extension<PageBuilder>) -> PageResult {
+    page.html()
+        .layout(MARKDOWN_LAYOUT)
+        .template("templates/content/volunteer")
+        .send()
 }
 
-pub async fn donate(Extension(tera): Extension<Arc<Tera>>) -> impl IntoResponse {
-    TeraTemplate::builder()
-        .tera(&tera)
-        .template("content/donate.tera")
-        .build()
+pub async fn donate(Extension(page): Extension<PageBuilder>) -> PageResult {
+    page.html()
+        .layout(MARKDOWN_LAYOUT)
+        .template("templates/content/donate")
+        .send()
 }
 
-pub async fn host_us(Extension(tera): Extension<Arc<Tera>>) -> impl IntoResponse {
-    TeraTemplate::builder()
-        .tera(&tera)
-        .template("content/host_us.tera")
-        .build()
+pub async fn host_us(Extension(page): Extension<PageBuilder>) -> PageResult {
+    page.html()
+        .layout(MARKDOWN_LAYOUT)
+        .template("templates/content/host_us")
+        .send()
 }
 
-pub async fn trip_map(Extension(tera): Extension<Arc<Tera>>) -> impl IntoResponse {
-    TeraTemplate::builder()
-        .tera(&tera)
-        .template("content/map.tera")
-        .build()
+pub async fn trip_map(Extension(page): Extension<PageBuilder>) -> PageResult {
+    page.html()
+        .layout(MAIN_LAYOUT)
+        .template("templates/content/map")
+        .send()
 }
 
-pub async fn updates(Extension(tera): Extension<Arc<Tera>>) -> impl IntoResponse {
-    TeraTemplate::builder()
-        .tera(&tera)
-        .template("content/updates.tera")
-        .build()
+pub async fn updates(Extension(page): Extension<PageBuilder>) -> PageResult {
+    page.html()
+        .layout(MARKDOWN_LAYOUT)
+        .template("templates/content/updates")
+        .send()
 }
@@ -4,31 +4,52 @@ use axum::Router;
 use axum::response::{IntoResponse, Redirect};
 use axum::routing::get;
 use deadpool_postgres::Pool;
-use tera::Tera;
+use loki::{CacheRegistry, InvalidationBus};
 
 use crate::config::Config;
 
 mod home;
 mod news;
 
-pub use news::MarkdownFilter;
+pub use home::CONTENT_RESPONSES;
+pub use news::NEWS_RESPONSES;
 
-pub fn create_routes(_config: &Config, database_pool: &Pool, tera: Arc<Tera>) -> Router {
-    let news_routes = news::news_routes(database_pool);
+/// The layout every page of the site extends
+pub const MAIN_LAYOUT: &str = "templates/layout/main";
 
-    Router::new()
+/// The layout of the markdown content pages, unless their front matter picks another
+pub const MARKDOWN_LAYOUT: &str = "templates/layout/markdown";
+
+pub fn create_routes(
+    _config: &Config,
+    database_pool: &Pool,
+    invalidation: &Arc<InvalidationBus>,
+) -> Router {
+    let news_routes = news::news_routes(database_pool, invalidation);
+
+    let content_routes = Router::new()
         .route("/", get(home::home))
-        .route("/home", get(home_redirect))
         .route("/map", get(home::trip_map))
         .route("/donate", get(home::donate))
         .route("/volunteer", get(home::volunteer))
         .route("/host", get(home::host_us))
         .route("/updates", get(home::updates))
+        .layer(home::CONTENT_RESPONSES.layer());
+
+    Router::new()
+        .merge(content_routes)
+        .route("/home", get(home_redirect))
         .nest("/news", news_routes)
-    /* .merge(crate::authentication::login_routes(
-        database_pool.clone(),
-        tera.clone(),
-    ))*/
+    /* .merge(crate::authentication::login_routes(database_pool.clone()))*/
+}
+
+/// Names the caches of the site so they can be inspected from `/admin/caches`, the
+/// registry registers them with its [InvalidationBus] so they're cleared across every
+/// instance
+pub fn register_caches(caches: &CacheRegistry) {
+    caches.register(news::NEWS_CACHE_NAME, news::NEWS_CACHE.as_ref());
+    caches.register("news_responses", &news::NEWS_RESPONSES);
+    caches.register("content_responses", &home::CONTENT_RESPONSES);
 }
 
 async fn home_redirect() -> impl IntoResponse {
@@ -1,18 +1,25 @@
-use std::{str::FromStr, sync::Arc};
+use std::{
+    str::FromStr,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
 
 use axum::{
     Extension, Form, Router,
-    body::Body,
     http::{StatusCode, Uri, header},
     response::{IntoResponse, Redirect, Response},
     routing::{get, post},
 };
 use deadpool_postgres::Pool;
-use loki::Cache;
+use loki::{
+    Backoff, Cache, ErrorDetails, InvalidationBus, PageBuilder, PageMetadata, PageResult,
+    ResponseCache,
+};
 use serde::{Deserialize, Serialize};
-use tera::{Context, Tera};
+use serde_json::json;
 use tracing::instrument;
 
+use super::MAIN_LAYOUT;
 use crate::services::{NewsItem, NewsStore};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -43,7 +50,7 @@ impl NewsForm {
             Some(url) if url.is_empty() => {
                 result.push("A location for news article is required".to_owned())
             }
-            Some(url) => match Uri::from_str(&url) {
+            Some(url) => match Uri::from_str(url) {
                 Ok(_) => {}
                 Err(e) => result.push(format!("The provided location is invalid '{e}'")),
             },
@@ -70,9 +77,36 @@ impl From<NewsForm> for NewsItem {
     }
 }
 
-pub static NEWS_CACHE: Cache<Vec<NewsItem>> = Cache::const_always();
+/// Tags everything cached from the news articles, editing an article clears them all
+pub const NEWS_TAG: &str = "news";
+
+/// The public news list rarely changes and edits clear it, so visitors are served the
+/// cached list while it refreshes and during database outages, failed fetches back off
+/// so an outage isn't made worse by every visitor retrying the query
+pub static NEWS_CACHE: LazyLock<Arc<Cache<Vec<NewsItem>>>> = LazyLock::new(|| {
+    Arc::new(
+        Cache::const_stale(
+            Duration::from_secs(60),
+            Duration::from_secs(15 * 60),
+            Duration::from_secs(60 * 60),
+        )
+        .with_backoff(
+            Backoff::exponential(Duration::from_secs(5), Duration::from_secs(5 * 60))
+                .keep_last_value(),
+        )
+        .with_tags(&[NEWS_TAG]),
+    )
+});
+
+/// The rendered public news page, clients revalidate on every request so edits show
+/// up as soon as the tag is invalidated
+pub static NEWS_RESPONSES: ResponseCache =
+    ResponseCache::const_ttl(16, Duration::from_secs(60)).with_tags(&[NEWS_TAG]);
 
-pub fn news_routes(database_pool: &Pool) -> Router {
+/// The name the news cache is registered under with the [InvalidationBus]
+pub const NEWS_CACHE_NAME: &str = "news";
+
+pub fn news_routes(database_pool: &Pool, invalidation: &Arc<InvalidationBus>) -> Router {
     let news_store = Arc::new(
         NewsStore::builder()
             .database_pool(database_pool.clone())
@@ -81,61 +115,29 @@ pub fn news_routes(database_pool: &Pool) -> Router {
 
     let user_routes = Router::new()
         .route("/", get(news))
-        .layer(Extension(news_store.clone()));
+        .layer(Extension(news_store.clone()))
+        .layer(NEWS_RESPONSES.layer());
 
     let admin_routes = Router::new()
         .route("/admin", get(list_news))
         .route("/admin", post(manage_mews))
-        .layer(Extension(news_store.clone()));
+        .layer(Extension(news_store.clone()))
+        .layer(Extension(invalidation.clone()));
 
     user_routes.merge(admin_routes)
 }
 
-fn tera_response<T>(tera: &Tera, template: T, context: &Context) -> Response
-where
-    T: AsRef<str>,
-{
-    match tera.render(template.as_ref(), context) {
-        Ok(html) => {
-            tracing::debug!(
-                context = ?context,
-                "rendering template '{}'",
-                template.as_ref()
-            );
-            match Response::builder()
-                .header(header::CONTENT_TYPE, "text/html")
-                .body(Body::from(html))
-            {
-                Ok(response) => response,
-                Err(e) => {
-                    tracing::error!(
-                        error = ?e,
-                        context = ?context,
-                        template = template.as_ref(),
-                        "failed to generate response: {e}"
-                    );
-                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                }
-            }
-        }
-        Err(e) => {
-            tracing::error!(
-                error = ?e,
-                context = ?context,
-                template = template.as_ref(),
-                "{e}"
-            );
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
-        }
+/// Clears everything derived from the news on every instance, failing to notify the
+/// other instances is logged as they'll pick up the change once their caches expire
+async fn invalidate_news(invalidation: &InvalidationBus) {
+    if let Err(e) = invalidation.invalidate_tag(NEWS_TAG).await {
+        tracing::error!("failed to invalidate the cached news: {e}");
     }
 }
 
-async fn news(
-    Extension(news_store): Extension<Arc<NewsStore>>,
-    Extension(tera): Extension<Arc<Tera>>,
-) -> std::result::Result<Response, StatusCode> {
+async fn news(Extension(news_store): Extension<Arc<NewsStore>>, page: PageBuilder) -> Response {
     let news = NEWS_CACHE
-        .try_fetch(async move {
+        .try_fetch_stale(async move {
             news_store.all().await.map(|articles| {
                 articles
                     .into_iter()
@@ -144,45 +146,98 @@ async fn news(
             })
         })
         .await
+        .inspect_err(|e| tracing::error!("failed to fetch news: {e}"))
         .unwrap_or_default();
 
-    let mut context = Context::new();
-    context.insert("news", &news);
+    let degraded = NEWS_CACHE.failure().await.is_some();
+    let mut response = page
+        .html()
+        .layout(MAIN_LAYOUT)
+        .template("templates/content/news")
+        .metadata(news_metadata(&news))
+        .value("news", news)
+        .value("degraded", degraded)
+        .send()
+        .into_response();
+    if degraded {
+        // the notice shouldn't outlive the outage in the response cache
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_static("no-store"),
+        );
+    }
+    response
+}
 
-    Ok(tera_response(&tera, "content/news.tera", &context))
+/// Describes the linked articles to search engines as a list of news articles
+fn news_metadata(news: &[NewsItem]) -> PageMetadata {
+    let articles = news
+        .iter()
+        .enumerate()
+        .map(|(position, article)| {
+            json!({
+                "@type": "ListItem",
+                "position": position + 1,
+                "item": {
+                    "@type": "NewsArticle",
+                    "headline": article.title,
+                    "url": article.url,
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    PageMetadata::builder()
+        .structured_data(json!({
+            "@context": "https://schema.org",
+            "@type": "ItemList",
+            "itemListElement": articles,
+        }))
+        .build()
 }
 
 async fn list_news(
     Extension(news_store): Extension<Arc<NewsStore>>,
-    Extension(tera): Extension<Arc<Tera>>,
-) -> std::result::Result<Response, StatusCode> {
+    page: PageBuilder,
+) -> PageResult {
     let news = news_store.all().await.unwrap_or_default();
-    let mut context = Context::new();
-    context.insert("articles", &news);
-    Ok(tera_response(&tera, "content/news/list.tera", &context))
+    page.html()
+        .layout(MAIN_LAYOUT)
+        .template("templates/content/news/list")
+        .metadata(
+            PageMetadata::builder()
+                .robots(vec![String::from("noindex")])
+                .build(),
+        )
+        .value("articles", news)
+        .send()
 }
 
-#[instrument(level = "info", skip(news_store, tera))]
+#[instrument(level = "info", skip(news_store, invalidation, page))]
 pub async fn manage_mews(
     Extension(news_store): Extension<Arc<NewsStore>>,
-    Extension(tera): Extension<Arc<Tera>>,
+    Extension(invalidation): Extension<Arc<InvalidationBus>>,
+    page: PageBuilder,
     Form(news_form): Form<NewsForm>,
 ) -> Response {
-    let mut context = tera::Context::new();
+    let mut page = page
+        .html()
+        .layout(MAIN_LAYOUT)
+        .template("templates/content/news/edit");
 
-    match news_form.action.as_ref().map(String::as_str) {
+    match news_form.action.as_deref() {
         Some("preview") => {
             let preview_item: NewsItem = news_form.into();
-            context.insert("preview_item", &preview_item);
-            context.insert("newsItem", &preview_item);
+            page = page.value("preview_item", &preview_item);
+            page = page.value("newsItem", preview_item);
         }
         Some("save") => {
             let news_item: NewsItem = news_form.clone().into();
-            context.insert("newsItem", &news_item);
+            page = page.value("newsItem", news_item);
             let errors = news_form.validate();
 
             if errors.is_some() {
-                context.insert("errors", &errors);
+                page = page.value("errors", errors);
             } else {
                 match news_store.save(&news_form.into()).await {
                     Ok(news_item) => {
@@ -190,33 +245,46 @@ pub async fn manage_mews(
                             article = ?news_item,
                             "saved news article"
                         );
-                        NEWS_CACHE.clear().await;
+                        invalidate_news(&invalidation).await;
                         return Redirect::to("/news/admin").into_response();
                     }
                     Err(e) => {
-                        context.insert("error_message", &e.to_string());
+                        page = page.value("error_message", e.to_string());
                     }
                 }
             }
         }
         Some("create") => {
-            context.insert("newsItem", &NewsItem::for_create());
+            page = page.value("newsItem", NewsItem::for_create());
         }
         Some("update") => {
             let Some(id) = news_form.id else {
                 tracing::error!("request was missing article identifier");
-                return StatusCode::NOT_FOUND.into_response();
+                return (
+                    StatusCode::NOT_FOUND,
+                    ErrorDetails::message("No news article was selected"),
+                )
+                    .into_response();
             };
 
             match news_store.get(id).await {
-                Ok(Some(news_item)) => context.insert("newsItem", &news_item),
+                Ok(Some(news_item)) => page = page.value("newsItem", news_item),
                 Ok(None) => {
                     tracing::error!("a request for invalid article '{id}' was submitted");
-                    return StatusCode::NOT_FOUND.into_response();
+                    return (
+                        StatusCode::NOT_FOUND,
+                        ErrorDetails::message("The news article no longer exists"),
+                    )
+                        .into_response();
                 }
                 Err(e) => {
                     tracing::error!("failed to retrieve article {id}: {e}");
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        ErrorDetails::message("The news article could not be loaded")
+                            .with_details(e.to_string()),
+                    )
+                        .into_response();
                 }
             }
         }
@@ -228,11 +296,11 @@ pub async fn manage_mews(
                         "deleted news article {}",
                         news_form.id.unwrap_or_default()
                     );
-                    NEWS_CACHE.clear().await;
+                    invalidate_news(&invalidation).await;
                     return Redirect::to("/news/admin").into_response();
                 }
                 Err(e) => {
-                    context.insert("error_message", &e.to_string());
+                    page = page.value("error_message", e.to_string());
                 }
             }
         }
@@ -245,28 +313,5 @@ pub async fn manage_mews(
         }
     }
 
-    tera_response(&tera, "content/news/edit.tera", &context)
-}
-
-pub struct MarkdownFilter;
-
-impl tera::Filter for MarkdownFilter {
-    fn filter(
-        &self,
-        value: &tera::Value,
-        _args: &std::collections::HashMap<String, tera::Value>,
-    ) -> tera::Result<tera::Value> {
-        match value {
-            serde_json::Value::String(markdown_text) => {
-                Ok(serde_json::Value::String(markdown::to_html(&markdown_text)))
-            }
-            _ => Err(tera::Error::msg(format!(
-                "markdown can only be used on string values: {value:?}"
-            ))),
-        }
-    }
-
-    fn is_safe(&self) -> bool {
-        true
-    }
+    page.send().into_response()
 }
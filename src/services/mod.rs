@@ -1,4 +1,6 @@
 mod news_store;
+// only used by the login flow, which isn't merged into the routes yet
+#[allow(dead_code)]
 mod user_store;
 
 pub use news_store::{NewsItem, NewsStore};
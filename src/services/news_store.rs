@@ -152,13 +152,13 @@ impl NewsStore {
                 tracing::debug!(
                     article = ?news_item,
                     "updating existing article {}",
-                    news_item.id.clone().unwrap_or(0)
+                    news_item.id.unwrap_or(0)
                 );
-                self.update(&news_item).await
+                self.update(news_item).await
             }
             None => {
                 tracing::debug!(article = ?news_item, "creating new article");
-                self.create(&news_item).await
+                self.create(news_item).await
             }
         }
     }
@@ -131,8 +131,8 @@ impl UserStore {
 
     pub async fn save(&self, user: &User) -> Result<User> {
         match user.id {
-            Some(_id) => self.update(&user).await,
-            None => self.create(&user).await,
+            Some(_id) => self.update(user).await,
+            None => self.create(user).await,
         }
     }
 